@@ -1,19 +1,38 @@
+pub mod auth;
+pub mod batches;
+pub mod cache;
+pub mod disk;
 pub mod downloader;
+pub mod lex;
+pub mod library;
+pub mod partial;
+pub mod schedule;
+pub mod sync;
 
 use crate::prelude::*;
-use downloader::{download_playlist, Resolution};
-use tokio_util::sync::CancellationToken;
+use auth::{CredentialStore, Credentials, Token};
+use batches::{BatchHandle, BatchId, BatchInfo, BatchKind, DownloadManager, QueuedLecture};
+use downloader::{
+    download_playlist, throttle::BandwidthLimit, HttpConfig, Playlist, Resolution, Views,
+};
+use lex::{Lecture, Subject};
+use library::{archive, LibraryEntry, VerifiedEntry};
+use schedule::{SyncRun, SyncSchedule};
+use sync::{Subscription, SyncReport};
 use tracing::{error, info};
 
 use tokio::sync::mpsc;
 
-use std::{ops::DerefMut, path::PathBuf, sync::Arc};
-use tauri::{ipc::Channel, AppHandle, Manager, State};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tauri::{ipc::Channel, AppHandle, Emitter, Manager, State};
 use tauri_plugin_shell::{
-    process::{CommandEvent, TerminatedPayload},
+    process::{CommandChild, CommandEvent, TerminatedPayload},
     ShellExt,
 };
-use tokio::{io::AsyncWriteExt, sync::Mutex, task::JoinSet};
+use tokio::{io::AsyncWriteExt, task::JoinSet};
 
 #[derive(serde::Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -23,6 +42,9 @@ pub struct Video {
     subject_name: String,
     number: i32,
     start_time: String,
+    /// A file already in the library to download over, for re-downloads
+    #[serde(skip)]
+    replace: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -30,16 +52,70 @@ pub struct DownloadProgressEvent {
     percent: f32,
 }
 
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BatchStartedEvent {
+    id: BatchId,
+}
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct DownloadErrorEvent {
     errors: Vec<String>,
 }
 
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DownloadCancelledEvent {
+    batch: BatchId,
+    ttid: i32,
+    number: i32,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DiskSpaceEvent {
+    low: bool,
+    /// In bytes
+    available: u64,
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Settings {
     resolution: Resolution,
     base: Option<String>,
     format: Option<String>,
+    #[serde(default)]
+    credential_store: CredentialStore,
+    #[serde(default)]
+    sync_schedule: SyncSchedule,
+    /// Downloads pause when the cache or download folder has less than this many MiB free
+    #[serde(default = "default_min_free_space")]
+    min_free_space: u64,
+    /// Folder to keep partial downloads in, instead of the system's temp directory
+    #[serde(default)]
+    cache_dir: Option<String>,
+    /// In MiB. Once the cache grows past this, the least recently used lectures are removed
+    #[serde(default)]
+    max_cache_size: Option<u64>,
+    /// Keep the original chunks, playlists and key of each lecture in a bundle next to it
+    #[serde(default)]
+    archive: bool,
+    #[serde(default)]
+    bandwidth: BandwidthLimit,
+    #[serde(default)]
+    http: HttpConfig,
+    /// Build lectures only from what's in the cache, without going online
+    #[serde(default)]
+    offline: bool,
+    /// How many lectures of a batch are downloaded at once, the rest wait in its queue. Three when
+    /// not set
+    #[serde(default)]
+    max_concurrent_lectures: Option<usize>,
+}
+
+fn default_min_free_space() -> u64 {
+    1024
+}
+
+fn default_max_concurrent_lectures() -> usize {
+    3
 }
 
 impl Default for Settings {
@@ -48,6 +124,16 @@ impl Default for Settings {
             resolution: Resolution::HighRes,
             base: None,
             format: None,
+            credential_store: CredentialStore::None,
+            sync_schedule: SyncSchedule::default(),
+            min_free_space: default_min_free_space(),
+            cache_dir: None,
+            max_cache_size: None,
+            archive: false,
+            bandwidth: BandwidthLimit::default(),
+            http: HttpConfig::default(),
+            offline: false,
+            max_concurrent_lectures: None,
         }
     }
 }
@@ -62,37 +148,80 @@ fn remove_special(string: impl AsRef<str>) -> String {
         .collect()
 }
 
-// TODO: Improve error handling
-#[instrument(fields(nth, ?video, %token, %folder, ?settings), skip_all)]
-async fn download_mp4(
-    settings: Arc<Settings>,
-    nth: usize,
-    tx: Arc<mpsc::Sender<(usize, f32)>>,
-    video: &Video,
-    token: Arc<String>,
-    folder: Arc<String>,
-    app: Arc<AppHandle>,
-) -> Result<i32, (i32, String)> {
+/// The name of a video's output file without its extension, following the user's format
+fn video_file_name(settings: &Settings, video: &Video) -> String {
     let Settings {
         resolution, format, ..
-    } = &*settings;
+    } = settings;
 
     let cleaned_topic = remove_special(&video.topic);
 
-    let default_video_file = format!("{}_{cleaned_topic}_{resolution}", video.number);
-
-    let video_file = if let Some(format) = format {
+    if let Some(format) = format {
         // A naive way to do this, but it works for now
-        &remove_special(
-            &format
+        remove_special(
+            format
                 .replace("{topic}", &cleaned_topic)
                 .replace("{number}", &video.number.to_string())
                 .replace("{resolution}", &resolution.to_string())
                 .replace("{date}", &video.start_time),
         )
     } else {
-        &default_video_file
-    };
+        format!("{}_{cleaned_topic}_{resolution}", video.number)
+    }
+}
+
+/// The folder a video is saved in, which is a folder named after its subject
+fn subject_folder(folder: &str, video: &Video) -> PathBuf {
+    let mut location = PathBuf::new().join(folder);
+    let subject_name = remove_special(&video.subject_name);
+
+    // Download in the given folder if the filename of the folder is the subject name
+    if location
+        .file_name()
+        .map(|v| v.to_str().unwrap_or(""))
+        .unwrap_or("")
+        != subject_name
+    {
+        info!(
+            "Given folder is not in folder with subject name {}. Adding subject folder",
+            subject_name
+        );
+        location.push(subject_name);
+    }
+
+    location
+}
+
+/// Where a video's mp4 is saved to
+fn output_location(settings: &Settings, video: &Video, folder: &str) -> PathBuf {
+    match &video.replace {
+        Some(existing) => existing.clone(),
+        None => {
+            subject_folder(folder, video).join(format!("{}.mp4", video_file_name(settings, video)))
+        }
+    }
+}
+
+// TODO: Improve error handling
+#[instrument(fields(nth, ?video, %folder, ?settings), skip_all)]
+async fn download_mp4(
+    settings: Arc<Settings>,
+    nth: usize,
+    tx: Arc<mpsc::Sender<(usize, f32)>>,
+    video: &Video,
+    token: Arc<Token>,
+    folder: Arc<String>,
+    app: Arc<AppHandle>,
+) -> Result<i32, (i32, String)> {
+    let resolution = settings.resolution;
+
+    let default_video_file = format!(
+        "{}_{}_{resolution}",
+        video.number,
+        remove_special(&video.topic)
+    );
+
+    let video_file = video_file_name(&settings, video);
 
     info!("download_mp4 invoked: Generating video_file name: {video_file}");
 
@@ -110,33 +239,18 @@ async fn download_mp4(
 
     info!("Checking download location");
 
-    let mut location = PathBuf::new().join(folder.to_string());
-    let subject_name = remove_special(&video.subject_name);
-
-    // Download in the given folder if the filename of the folder is the subject name
-    if location
-        .file_name()
-        .map(|v| v.to_str().unwrap_or(""))
-        .unwrap_or("")
-        != subject_name
-    {
-        info!(
-            "Given folder is not in folder with subject name {}. Adding subject folder",
-            subject_name
-        );
-        location.push(subject_name);
-    }
+    let location = output_location(&settings, video, &folder);
 
     // Create directory to store current subject lectures if not already created
-    tokio::fs::create_dir_all(&location)
-        .await
-        .context("creating subject download location")
-        .map_err(|e| (video.number, e.to_string()))?;
-
-    location.push(format!("{video_file}.mp4"));
+    if let Some(parent) = location.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .context("creating subject download location")
+            .map_err(|e| (video.number, e.to_string()))?;
+    }
 
-    // Skip this download if it exists
-    if location.exists() {
+    // Skip this download if it exists, unless it's being downloaded again
+    if video.replace.is_none() && tokio::fs::try_exists(&location).await.unwrap_or(false) {
         // Say it's at 100%
         let _ = tx.send((nth, 100.0)).await;
         return Ok(video.ttid);
@@ -144,8 +258,14 @@ async fn download_mp4(
 
     info!("Starting download of m3u8 playlist");
 
-    let (side1, side2) = download_playlist(
-        settings,
+    let Playlist {
+        side1,
+        side2,
+        key,
+        duration,
+        views,
+    } = download_playlist(
+        Arc::clone(&settings),
         itx,
         &token,
         video.ttid as usize,
@@ -171,42 +291,26 @@ async fn download_mp4(
         )
     })?;
 
-    let mut args = vec![
-        "-allowed_extensions",
-        "ALL",
-        "-nostdin", // Prevent ffmpeg from pausing for user input
-        "-i",
-        &side1,
-        "-c",
-        "copy",
-        location_str,
-    ];
-
-    if let Some(side2) = &side2 {
-        args = vec![
-            "-allowed_extensions",
-            "ALL",
-            "-nostdin",
-            "-i",
-            &side1,
-            "-allowed_extensions",
-            "ALL",
-            "-i",
-            side2,
-            "-map",
-            "0",
-            "-map",
-            "1",
-            "-c",
-            "copy",
-            location_str,
-        ]
-    }
+    let partial = partial::path_for(&location);
+    let partial_str = partial.to_str().ok_or(()).map_err(|_| {
+        (
+            video.number,
+            "Failed to access provided download location!".to_string(),
+        )
+    })?;
+
+    let args = ffmpeg_args(&side1, side2.as_deref(), partial_str);
+
+    let app_data = app
+        .path()
+        .app_data_dir()
+        .context("reading app data dir")
+        .map_err(|e| (video.number, e.to_string()))?;
 
     info!("Checking again if the file exists");
 
     // Throw an error now if the file has been created between download and ffmpeg spawn
-    if location.exists() {
+    if video.replace.is_none() && tokio::fs::try_exists(&location).await.unwrap_or(false) {
         error!("The file `{location_str}` already exists! It was likely created or moved into the directory when the download operation started.");
         return Err((
             video.number,
@@ -214,16 +318,30 @@ async fn download_mp4(
         ));
     }
 
+    // The output is about as large as all of its chunks
+    disk::wait_for_space(&partial).await;
+
+    partial::begin(&app_data, &partial)
+        .await
+        .map_err(|e| (video.number, e.to_string()))?;
+
     info!("Spawning ffmpeg");
 
     let ffmpeg = ffmpeg.args(args.as_slice());
 
     let mut ffmpeg_errors = String::new();
-    let (mut rx, _child) = ffmpeg
+    let (mut rx, child) = ffmpeg
         .spawn()
         .context("spawn ffmpeg")
         .map_err(|e| (video.number, e.to_string()))?;
 
+    // Cleans up after ffmpeg if this download is cancelled while it's running
+    let mux = MuxGuard {
+        child: Some(child),
+        partial: partial.clone(),
+        app_data: app_data.clone(),
+    };
+
     info!("ffmpeg spawned");
 
     let _ = tx.try_send((nth, 50.0));
@@ -256,10 +374,8 @@ async fn download_mp4(
                 ffmpeg_errors += "\n";
             }
 
-            // 0 = successful exit, 4 = user cancelled
-            CommandEvent::Terminated(TerminatedPayload {
-                code: Some(0 | 4), ..
-            }) => {
+            // Anything but a clean exit, including being interrupted, leaves a broken output
+            CommandEvent::Terminated(TerminatedPayload { code: Some(0), .. }) => {
                 ffmpeg_errors.clear();
             }
 
@@ -267,32 +383,189 @@ async fn download_mp4(
         }
     }
 
+    // ffmpeg has exited by itself
+    mux.disarm();
+
     if !ffmpeg_errors.is_empty() {
         info!("ffmpeg failed with: \n{ffmpeg_errors}");
+        let _ = tokio::fs::remove_file(&partial).await;
+        let _ = partial::forget(&app_data, &partial).await;
         return Err((video.number, ffmpeg_errors));
     }
 
+    // A re-download only replaces the old file now that it has finished
+    partial::commit(&app_data, &location, video.replace.is_some())
+        .await
+        .map_err(|e| (video.number, e.to_string()))?;
+
     info!(
         "ffmpeg completed generation of output mp4 for {} at `{}`",
         video.ttid,
         location.to_str().unwrap_or("")
     );
 
+    // Keep the original chunks around to remux later, if the user wants to
+    let bundle = if settings.archive {
+        let bundle = location.with_extension(archive::EXTENSION);
+        match archive::pack(&side1, side2.as_deref(), &key, &bundle).await {
+            Ok(()) => Some(bundle),
+            Err(e) => {
+                error!("Failed to archive lecture {}: {e}", video.ttid);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // The download itself has succeeded, so failing to index it is only logged
+    let _ = record_in_library(&app, &settings, video, &location, bundle, duration, views)
+        .await
+        .inspect_err(|e| error!("Failed to record lecture {} in library: {e}", video.ttid));
+
     let _ = tx.try_send((nth, 100.0));
     Ok(video.ttid)
 }
 
-fn get_temp() -> PathBuf {
-    std::env::temp_dir()
-        .join("multipartus-downloader")
-        .join("videos")
+/// Kills ffmpeg and removes its half written output when dropped before being disarmed, which
+/// happens when a download is cancelled while it's muxing
+struct MuxGuard {
+    child: Option<CommandChild>,
+    partial: PathBuf,
+    app_data: PathBuf,
+}
+
+impl MuxGuard {
+    fn disarm(mut self) {
+        self.child = None;
+    }
+}
+
+impl Drop for MuxGuard {
+    fn drop(&mut self) {
+        let Some(child) = self.child.take() else {
+            return;
+        };
+
+        info!(
+            "Killing ffmpeg, which was writing `{}`",
+            self.partial.display()
+        );
+
+        let _ = child
+            .kill()
+            .inspect_err(|e| error!("Failed to kill ffmpeg: {e}"));
+
+        // Killing it is not instant, so it may still be holding on to the file
+        let (partial, app_data) = (self.partial.clone(), self.app_data.clone());
+        tokio::spawn(async move {
+            for _ in 0..10 {
+                match tokio::fs::remove_file(&partial).await {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                        tokio::time::sleep(std::time::Duration::from_millis(200)).await
+                    }
+                    _ => {
+                        let _ = partial::forget(&app_data, &partial).await;
+                        return;
+                    }
+                }
+            }
+            // Left in the journal for the next launch to sweep up
+            error!("Failed to remove partial output `{}`", partial.display());
+        });
+    }
+}
+
+/// Arguments for ffmpeg to copy one or both sides of a lecture into a single video
+fn ffmpeg_args<'a>(side1: &'a str, side2: Option<&'a str>, output: &'a str) -> Vec<&'a str> {
+    if let Some(side2) = side2 {
+        vec![
+            "-allowed_extensions",
+            "ALL",
+            "-nostdin",
+            "-i",
+            side1,
+            "-allowed_extensions",
+            "ALL",
+            "-i",
+            side2,
+            "-map",
+            "0",
+            "-map",
+            "1",
+            "-c",
+            "copy",
+            output,
+        ]
+    } else {
+        vec![
+            "-allowed_extensions",
+            "ALL",
+            "-nostdin", // Prevent ffmpeg from pausing for user input
+            "-i",
+            side1,
+            "-c",
+            "copy",
+            output,
+        ]
+    }
+}
+
+async fn record_in_library(
+    app: &AppHandle,
+    settings: &Settings,
+    video: &Video,
+    location: &Path,
+    bundle: Option<PathBuf>,
+    duration: f64,
+    views: Views,
+) -> Result<()> {
+    let app_data = app.path().app_data_dir().context("reading app data dir")?;
+
+    let size = tokio::fs::metadata(location)
+        .await
+        .context("reading output file size")?
+        .len();
+
+    let checksum = library::checksum(location).await?;
+
+    library::record(
+        &app_data,
+        LibraryEntry {
+            ttid: video.ttid,
+            subject: video.subject_name.clone(),
+            number: video.number,
+            topic: video.topic.clone(),
+            date: video.start_time.clone(),
+            resolution: settings.resolution,
+            views,
+            path: location.to_string_lossy().to_string(),
+            size,
+            duration,
+            checksum,
+            downloaded_at: chrono::Local::now(),
+            bundle: bundle.map(|bundle| bundle.to_string_lossy().to_string()),
+        },
+    )
+    .await
+}
+
+/// Where lectures are cached while they're downloaded
+fn get_temp(settings: &Settings) -> PathBuf {
+    match &settings.cache_dir {
+        // Kept in a folder of its own, since clearing the cache removes all of it
+        Some(cache_dir) => PathBuf::from(cache_dir).join("multipartus-downloader"),
+        None => std::env::temp_dir()
+            .join("multipartus-downloader")
+            .join("videos"),
+    }
 }
 
 #[tauri::command]
 #[instrument(skip_all)]
-pub async fn clear_cache() -> Result<(), String> {
+pub async fn clear_cache(app: AppHandle) -> Result<(), String> {
     info!("clear_cache command invoked");
-    let temp = get_temp();
+    let temp = get_temp(&get_resolved_settings(&app).await);
     tokio::fs::remove_dir_all(temp.as_path().to_str().unwrap_or("./tmp"))
         .await
         .inspect_err(|e| error!("error clearing cache: {e}"))
@@ -301,17 +574,13 @@ pub async fn clear_cache() -> Result<(), String> {
     Ok(())
 }
 
-// Should this run on another thread?
 #[tauri::command]
 #[instrument(skip_all)]
-pub fn get_cache_size() -> Result<String, String> {
+pub async fn get_cache_size(app: AppHandle) -> Result<cache::CacheSize, String> {
     info!("get_cache_size command invoked");
-    let temp = get_temp();
-    if !temp.exists() {
-        info!("Temp file for multipartus-downloader does not exist");
-        return Ok("0KiB".to_string());
-    }
-    dir_size::get_size_in_human_bytes(temp.as_path())
+    let temp = get_temp(&get_resolved_settings(&app).await);
+    cache::size(temp)
+        .await
         .inspect_err(|e| error!("failed getting temp dir size: {e}"))
         .context("getting temp dir size")
         .map_err(|e| e.to_string())
@@ -319,7 +588,11 @@ pub fn get_cache_size() -> Result<String, String> {
 
 #[tauri::command]
 #[instrument(skip_all)]
-pub async fn save_settings(app: AppHandle, settings: Settings) -> Result<(), String> {
+pub async fn save_settings(
+    token_store: State<'_, Arc<Token>>,
+    app: AppHandle,
+    settings: Settings,
+) -> Result<(), String> {
     info!("save_settings command invoked");
 
     // the .inspect_err.context.map_err waterfall is a lil fucked, but this is an top level fn.
@@ -332,7 +605,55 @@ pub async fn save_settings(app: AppHandle, settings: Settings) -> Result<(), Str
         .context("reading app data dir")
         .map_err(|e| e.to_string())?;
 
-    tokio::fs::create_dir_all(&app_data)
+    settings
+        .sync_schedule
+        .validate()
+        .inspect_err(|e| error!("invalid sync schedule: {e}"))
+        .map_err(|e| e.to_string())?;
+
+    settings
+        .bandwidth
+        .validate()
+        .inspect_err(|e| error!("invalid bandwidth limit: {e}"))
+        .map_err(|e| e.to_string())?;
+
+    // Catch a bad proxy or certificate before it's saved, but only use the client once it is
+    let client = downloader::build_client(&settings.http)
+        .await
+        .inspect_err(|e| error!("failed building http client: {e}"))
+        .map_err(|e| e.to_string())?;
+
+    let previous_store = get_resolved_settings(&app).await.credential_store;
+
+    write_settings(&app_data, &settings).await?;
+
+    info!("Saved new settings");
+
+    downloader::install_client(client, &settings.http);
+
+    // Don't leave credentials behind in a store the user has moved away from
+    if previous_store != settings.credential_store {
+        token_store
+            .switch_store(settings.credential_store)
+            .await
+            .inspect_err(|e| error!("failed moving credentials: {e}"))
+            .context("moving stored credentials")
+            .map_err(|e| e.to_string())?;
+
+        previous_store
+            .clear(app_data)
+            .await
+            .inspect_err(|e| error!("failed clearing credentials: {e}"))
+            .context("clearing stored credentials")
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+/// Writes settings to settings.json in the app data dir
+async fn write_settings(app_data: &Path, settings: &Settings) -> Result<(), String> {
+    tokio::fs::create_dir_all(app_data)
         .await
         .inspect_err(|e| error!("failed creating app data dir: {e}"))
         .context("creating app data dir")
@@ -344,7 +665,7 @@ pub async fn save_settings(app: AppHandle, settings: Settings) -> Result<(), Str
         .context("creating settings.json")
         .map_err(|e| e.to_string())?;
 
-    let json = serde_json::to_string(&settings)
+    let json = serde_json::to_string(settings)
         .inspect_err(|e| {
             error!("failed at serializing settings: {e}");
             trace!(?settings);
@@ -358,8 +679,6 @@ pub async fn save_settings(app: AppHandle, settings: Settings) -> Result<(), Str
         .context("writing settings.json")
         .map_err(|e| e.to_string())?;
 
-    info!("Saved new settings");
-
     Ok(())
 }
 
@@ -419,62 +738,128 @@ pub fn log_error(error: String) -> Result<(), String> {
 }
 
 #[tauri::command]
-#[instrument(fields(token, folder), skip_all)]
+#[instrument(fields(folder), skip_all)]
+#[allow(clippy::too_many_arguments)]
 pub async fn download(
-    cancellation_token: State<'_, Mutex<CancellationToken>>,
+    downloads: State<'_, Arc<DownloadManager>>,
+    token_store: State<'_, Arc<Token>>,
     app: AppHandle,
     token: String,
     folder: String,
     videos: Vec<Video>,
+    on_start: Channel<BatchStartedEvent>,
     on_progress: Channel<DownloadProgressEvent>,
     on_error: Channel<DownloadErrorEvent>,
 ) -> Result<(), String> {
     info!("download command invoked");
 
-    // Reset the cancellation token
-    let cancellation_token = {
-        let mut old_cancellation_token = cancellation_token.lock().await;
-        *(old_cancellation_token.deref_mut()) = CancellationToken::new();
-        old_cancellation_token.clone()
-    };
+    let batch = downloads.start(&app, BatchKind::Download, Some(videos.len()));
+    let _ = on_start.send(BatchStartedEvent { id: batch.id() });
+
+    // Chunk requests pick up the token from the store, so it can be refreshed mid-download
+    token_store.set(token);
+
+    let progress_batch = batch.clone();
+    let result = download_videos(
+        app,
+        Arc::clone(&token_store),
+        folder,
+        videos,
+        batch.clone(),
+        move |percent| {
+            progress_batch.set_progress(percent);
+            let _ = on_progress.send(DownloadProgressEvent { percent });
+        },
+        |errors| {
+            let _ = on_error.send(DownloadErrorEvent { errors });
+        },
+    )
+    .await;
+
+    batch.finish(&result);
+    result?;
+
+    Ok(())
+}
 
+/// Downloads the videos into the folder, reporting the overall progress and each failure as
+/// they happen. Returns the ttids of the videos that were downloaded
+async fn download_videos(
+    app: AppHandle,
+    token: Arc<Token>,
+    folder: String,
+    videos: Vec<Video>,
+    batch: BatchHandle,
+    on_progress: impl Fn(f32) + Send + 'static,
+    on_error: impl Fn(Vec<String>),
+) -> Result<Vec<i32>, String> {
     let settings = Arc::new(get_resolved_settings(&app).await);
 
-    let token = Arc::new(token);
+    downloader::throttle::configure(settings.bandwidth.clone());
+
+    let watch_app = app.clone();
+    let _space_watcher = disk::SpaceWatcher::spawn(
+        vec![get_temp(&settings), PathBuf::from(&folder)],
+        settings.min_free_space * 1024 * 1024,
+        move |low, available| {
+            let _ = watch_app.emit("disk-space", DiskSpaceEvent { low, available });
+        },
+    );
+
     let folder = Arc::new(folder);
     let app = Arc::new(app);
+    let cancellation_token = batch.cancellation_token();
 
     let mut set = JoinSet::new();
 
+    let refused = batch.enqueue(videos.iter().map(|video| {
+        info!("Queuing download of {}", video.ttid);
+        QueuedLecture {
+            ttid: video.ttid,
+            number: video.number,
+            topic: video.topic.clone(),
+            priority: 0,
+        }
+    }));
+
+    for lecture in &refused {
+        warn!(
+            "Lecture {} is already being downloaded by another batch",
+            lecture.ttid
+        );
+        on_error(vec![
+            format!("Failed to download Lecture-{}", lecture.number),
+            "It's already being downloaded by another batch".to_string(),
+        ]);
+    }
+    let videos = videos
+        .into_iter()
+        .filter(|video| !refused.iter().any(|lecture| lecture.ttid == video.ttid))
+        .collect::<Vec<_>>();
+
     let num_videos = videos.len();
 
-    let (tx, mut rx) = tokio::sync::mpsc::channel(videos.len());
+    let (tx, mut rx) = tokio::sync::mpsc::channel(videos.len().max(1));
 
     let tx = Arc::new(tx);
 
-    for (i, video) in videos.into_iter().enumerate() {
-        info!("Queuing download of {}", video.ttid);
-
-        let (token, app, folder, cancel_token, settings) = (
-            token.clone(),
-            app.clone(),
-            folder.clone(),
-            cancellation_token.clone(),
-            settings.clone(),
-        );
-        let tx = tx.clone();
+    let mut pending = videos.into_iter().enumerate().collect::<Vec<_>>();
 
-        set.spawn(async move {
-            tokio::select! {
-                _ = cancel_token.cancelled() => {
-                    info!("Cancelled download of {}", video.ttid);
-                    Err((video.number, "Cancelled".to_string()))
-                }
-                // does this need to be cancel safe?
-                result = download_mp4(settings, i, tx, &video, token, folder, app) => result,
-            }
-        });
+    // Make room for this batch by dropping lectures that were abandoned part way through, keeping
+    // those that this or any other running batch still needs
+    if let Some(max_cache_size) = settings.max_cache_size {
+        let keep = batch.lectures_in_use();
+        let _ = cache::evict(&get_temp(&settings), max_cache_size * 1024 * 1024, &keep)
+            .await
+            .inspect_err(|e| error!("Failed to evict lectures from the cache: {e}"));
     }
+
+    let max_concurrent = settings
+        .max_concurrent_lectures
+        .unwrap_or_else(default_max_concurrent_lectures)
+        .max(1);
+    let batch_id = batch.id();
+
     // Send progress as each download task sends a message through the mpsc channel
     tokio::spawn(async move {
         let mut channels = vec![0.0; num_videos];
@@ -483,46 +868,875 @@ pub async fn download(
         while let Some((nth, progress)) = rx.recv().await {
             channels[nth] = progress;
             let avg_progress = channels.iter().sum::<f32>() / (num_videos as f32);
-            let _ = on_progress.send(DownloadProgressEvent {
-                percent: avg_progress,
-            });
+            on_progress(avg_progress);
         }
     });
 
-    while let Some(res) = set.join_next().await {
+    let mut downloaded = Vec::with_capacity(num_videos);
+
+    loop {
+        // The queue may have been reordered while the last lecture was downloading, so the next
+        // one is only picked once there's room for it
+        while set.len() < max_concurrent {
+            let (i, video) = match batch.next_queued() {
+                Some(next) => {
+                    match pending
+                        .iter()
+                        .position(|(_, video)| video.ttid == next.ttid)
+                    {
+                        Some(index) => pending.remove(index),
+                        None => {
+                            error!(
+                                "Lecture {} was queued, but isn't pending in batch {batch_id}",
+                                next.ttid
+                            );
+                            on_error(vec![
+                                format!("Failed to download Lecture-{}", next.number),
+                                "It was queued, but isn't part of this batch".to_string(),
+                            ]);
+                            continue;
+                        }
+                    }
+                }
+                // Anything the queue lost track of is still downloaded, in the order it was given
+                None if !pending.is_empty() => {
+                    warn!("Queue of batch {batch_id} ran out with lectures still pending");
+                    pending.remove(0)
+                }
+                None => break,
+            };
+
+            if cancellation_token.is_cancelled() {
+                info!("Cancelled queued download of {}", video.ttid);
+                emit_cancelled(&app, batch_id, &video);
+                on_error(vec![
+                    format!("Failed to download Lecture-{}", video.number),
+                    "Cancelled".to_string(),
+                ]);
+                continue;
+            }
+
+            info!("Starting download of {}", video.ttid);
+
+            let (token, app, folder, cancel_token, settings) = (
+                token.clone(),
+                app.clone(),
+                folder.clone(),
+                cancellation_token.clone(),
+                settings.clone(),
+            );
+            let tx = tx.clone();
+
+            set.spawn(async move {
+                let status_app = Arc::clone(&app);
+                tokio::select! {
+                    _ = cancel_token.cancelled() => {
+                        info!("Cancelled download of {}", video.ttid);
+                        // The download has been dropped by now, so nothing of it is left running
+                        emit_cancelled(&status_app, batch_id, &video);
+                        Err((video.number, "Cancelled".to_string()))
+                    }
+                    // does this need to be cancel safe?
+                    result = download_mp4(settings, i, tx, &video, token, folder, app) => result,
+                }
+            });
+        }
+
+        let Some(res) = set.join_next().await else {
+            break;
+        };
+
         match res.map_err(|e| e.to_string())? {
             Err((number, err)) => {
                 error!("Failed to download Lecture-{number}: {err}");
-                let _ = on_error.send(DownloadErrorEvent {
-                    errors: vec![format!("Failed to download Lecture-{number}"), err],
-                });
+                on_error(vec![format!("Failed to download Lecture-{number}"), err]);
             }
 
             Ok(ttid) => {
                 info!("Deleting lecture {} from cache", ttid);
                 // This lecture download has completed, remove it from the cache
-                let remove_loc = get_temp().join(format!("Lecture_{}", ttid));
+                let remove_loc = get_temp(&settings).join(format!("Lecture_{}", ttid));
 
                 let _ = tokio::fs::remove_dir_all(remove_loc)
                     .await
                     .inspect_err(|error| {
                         error!("Failed to remove download folder of lecture {ttid}: {error}");
                     });
+
+                downloaded.push(ttid);
             }
         };
     }
 
-    Ok(())
+    Ok(downloaded)
+}
+
+fn emit_cancelled(app: &AppHandle, batch: BatchId, video: &Video) {
+    let _ = app.emit(
+        "download-cancelled",
+        DownloadCancelledEvent {
+            batch,
+            ttid: video.ttid,
+            number: video.number,
+        },
+    );
 }
 
 #[tauri::command]
 #[instrument(skip_all)]
 pub async fn cancel_download(
-    cancellation_token: State<'_, Mutex<CancellationToken>>,
-) -> Result<(), ()> {
-    info!("Cancelling all running download tasks");
-    let token = cancellation_token.lock().await;
-    token.cancel();
-    info!("Cancelled all download tasks");
+    downloads: State<'_, Arc<DownloadManager>>,
+    id: BatchId,
+) -> Result<(), String> {
+    info!("cancel_download command invoked");
+
+    if !downloads.cancel(id) {
+        warn!("No running batch {id} to cancel");
+    }
     Ok(())
 }
+
+/// Lists the batches of downloads that are running, and those that finished since the last listing
+#[tauri::command]
+#[instrument(skip_all)]
+pub fn list_batches(downloads: State<'_, Arc<DownloadManager>>) -> Vec<BatchInfo> {
+    info!("list_batches command invoked");
+
+    downloads.list()
+}
+
+/// Moves a queued lecture to the front of its batch's queue
+#[tauri::command]
+#[instrument(skip_all, fields(batch, ttid))]
+pub fn bump_queued(
+    downloads: State<'_, Arc<DownloadManager>>,
+    batch: BatchId,
+    ttid: i32,
+) -> Result<(), String> {
+    info!("bump_queued command invoked");
+
+    downloads
+        .bump(batch, ttid)
+        .inspect_err(|e| error!("failed bumping lecture: {e}"))
+        .map_err(|e| e.to_string())
+}
+
+/// Moves a queued lecture to the back of its batch's queue
+#[tauri::command]
+#[instrument(skip_all, fields(batch, ttid))]
+pub fn defer_queued(
+    downloads: State<'_, Arc<DownloadManager>>,
+    batch: BatchId,
+    ttid: i32,
+) -> Result<(), String> {
+    info!("defer_queued command invoked");
+
+    downloads
+        .defer(batch, ttid)
+        .inspect_err(|e| error!("failed deferring lecture: {e}"))
+        .map_err(|e| e.to_string())
+}
+
+/// Puts the given queued lectures at the front of their batch's queue, in that order
+#[tauri::command]
+#[instrument(skip_all, fields(batch))]
+pub fn reorder_queue(
+    downloads: State<'_, Arc<DownloadManager>>,
+    batch: BatchId,
+    ttids: Vec<i32>,
+) -> Result<(), String> {
+    info!("reorder_queue command invoked");
+
+    downloads
+        .reorder(batch, &ttids)
+        .inspect_err(|e| error!("failed reordering queue: {e}"))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+#[instrument(skip_all)]
+pub fn set_token(token_store: State<'_, Arc<Token>>, token: String) -> Result<(), String> {
+    info!("set_token command invoked");
+    token_store.set(token);
+    Ok(())
+}
+
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn store_credentials(
+    token_store: State<'_, Arc<Token>>,
+    app: AppHandle,
+    refresh_token: String,
+) -> Result<(), String> {
+    info!("store_credentials command invoked");
+
+    let store = get_resolved_settings(&app).await.credential_store;
+    if store == CredentialStore::None {
+        return Ok(());
+    }
+
+    let app_data = app
+        .path()
+        .app_data_dir()
+        .inspect_err(|e| error!("error reading app data dir: {e}"))
+        .context("reading app data dir")
+        .map_err(|e| e.to_string())?;
+
+    store
+        .save(app_data.clone(), refresh_token.clone())
+        .await
+        .inspect_err(|e| error!("failed saving credentials: {e}"))
+        .context("saving credentials")
+        .map_err(|e| e.to_string())?;
+
+    token_store
+        .set_credentials(Some(Credentials {
+            store,
+            dir: app_data,
+            refresh_token,
+        }))
+        .await;
+
+    info!("Stored credentials in {store:?}");
+
+    Ok(())
+}
+
+/// Sets up the HTTP client from the saved settings
+pub async fn configure_http(app: AppHandle) {
+    let settings = get_resolved_settings(&app).await;
+
+    let _ = downloader::configure_client(&settings.http)
+        .await
+        .inspect_err(|e| error!("Failed to configure HTTP client, using defaults: {e}"));
+}
+
+/// Removes outputs that a previous session was still writing when it exited
+pub async fn sweep_partial_outputs(app: &AppHandle) {
+    let Ok(app_data) = app.path().app_data_dir() else {
+        error!("Failed to read app data dir while sweeping partial outputs");
+        return;
+    };
+
+    let _ = partial::sweep(&app_data)
+        .await
+        .inspect_err(|e| error!("Failed to sweep partial outputs: {e}"));
+}
+
+/// Hands credentials saved in a previous session to the token store
+#[instrument(skip_all)]
+pub async fn restore_credentials(app: AppHandle) {
+    let store = get_resolved_settings(&app).await.credential_store;
+
+    let Ok(app_data) = app.path().app_data_dir() else {
+        error!("Failed to read app data dir while restoring credentials");
+        return;
+    };
+
+    match store.load(app_data.clone()).await {
+        Ok(Some(refresh_token)) => {
+            app.state::<Arc<Token>>()
+                .set_credentials(Some(Credentials {
+                    store,
+                    dir: app_data,
+                    refresh_token,
+                }))
+                .await;
+            info!("Restored credentials from {store:?}");
+        }
+        Ok(None) => (),
+        Err(e) => error!("Failed to restore credentials: {e}"),
+    }
+}
+
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn list_subscriptions(app: AppHandle) -> Result<Vec<Subscription>, String> {
+    info!("list_subscriptions command invoked");
+
+    let app_data = app
+        .path()
+        .app_data_dir()
+        .inspect_err(|e| error!("error reading app data dir: {e}"))
+        .context("reading app data dir")
+        .map_err(|e| e.to_string())?;
+
+    sync::load_subscriptions(&app_data)
+        .await
+        .inspect_err(|e| error!("failed loading subscriptions: {e}"))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn search_subjects(
+    token_store: State<'_, Arc<Token>>,
+    token: String,
+    query: String,
+) -> Result<Vec<Subject>, String> {
+    info!("search_subjects command invoked");
+    token_store.set(token);
+
+    lex::search_subjects(&token_store, &query)
+        .await
+        .inspect_err(|e| error!("failed searching subjects: {e}"))
+        .context("searching subjects")
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn list_sessions(
+    token_store: State<'_, Arc<Token>>,
+    token: String,
+) -> Result<lex::Sessions, String> {
+    info!("list_sessions command invoked");
+    token_store.set(token);
+
+    lex::sessions(&token_store)
+        .await
+        .inspect_err(|e| error!("failed listing sessions: {e}"))
+        .context("listing sessions")
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn list_lectures(
+    token_store: State<'_, Arc<Token>>,
+    token: String,
+    subject: lex::SubjectId,
+) -> Result<Vec<Lecture>, String> {
+    info!("list_lectures command invoked");
+    token_store.set(token);
+
+    lex::lectures(&token_store, &subject)
+        .await
+        .inspect_err(|e| error!("failed listing lectures: {e}"))
+        .context("listing lectures")
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn subscribe(
+    app: AppHandle,
+    subject: Subject,
+    lecture: Lecture,
+    folder: String,
+) -> Result<(), String> {
+    info!("subscribe command invoked");
+
+    let app_data = app
+        .path()
+        .app_data_dir()
+        .inspect_err(|e| error!("error reading app data dir: {e}"))
+        .context("reading app data dir")
+        .map_err(|e| e.to_string())?;
+
+    let mut subscriptions = sync::load_subscriptions(&app_data)
+        .await
+        .inspect_err(|e| error!("failed loading subscriptions: {e}"))
+        .map_err(|e| e.to_string())?;
+
+    // Subscribing to a lecture again moves it to the new folder
+    subscriptions.retain(|subscription| subscription.lecture.id != lecture.id);
+    subscriptions.push(Subscription {
+        subject,
+        lecture,
+        folder,
+    });
+
+    sync::save_subscriptions(&app_data, &subscriptions)
+        .await
+        .inspect_err(|e| error!("failed saving subscriptions: {e}"))
+        .map_err(|e| e.to_string())?;
+
+    info!("Saved new subscription");
+
+    Ok(())
+}
+
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn unsubscribe(app: AppHandle, lecture: lex::LectureId) -> Result<(), String> {
+    info!("unsubscribe command invoked");
+
+    let app_data = app
+        .path()
+        .app_data_dir()
+        .inspect_err(|e| error!("error reading app data dir: {e}"))
+        .context("reading app data dir")
+        .map_err(|e| e.to_string())?;
+
+    let mut subscriptions = sync::load_subscriptions(&app_data)
+        .await
+        .inspect_err(|e| error!("failed loading subscriptions: {e}"))
+        .map_err(|e| e.to_string())?;
+
+    subscriptions.retain(|subscription| subscription.lecture.id.id != lecture);
+
+    sync::save_subscriptions(&app_data, &subscriptions)
+        .await
+        .inspect_err(|e| error!("failed saving subscriptions: {e}"))
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+#[tauri::command]
+#[instrument(skip_all, fields(dry_run))]
+#[allow(clippy::too_many_arguments)]
+pub async fn sync(
+    downloads: State<'_, Arc<DownloadManager>>,
+    token_store: State<'_, Arc<Token>>,
+    app: AppHandle,
+    token: String,
+    dry_run: bool,
+    on_start: Channel<BatchStartedEvent>,
+    on_progress: Channel<DownloadProgressEvent>,
+    on_error: Channel<DownloadErrorEvent>,
+) -> Result<SyncReport, String> {
+    info!("sync command invoked");
+
+    let app_data = app
+        .path()
+        .app_data_dir()
+        .inspect_err(|e| error!("error reading app data dir: {e}"))
+        .context("reading app data dir")
+        .map_err(|e| e.to_string())?;
+
+    let batch = downloads.start(&app, BatchKind::Sync, None);
+    let _ = on_start.send(BatchStartedEvent { id: batch.id() });
+
+    token_store.set(token);
+
+    let progress_batch = batch.clone();
+    let result = sync::sync(
+        &app,
+        &app_data,
+        Arc::clone(&token_store),
+        batch.clone(),
+        dry_run,
+        Arc::new(move |percent| {
+            progress_batch.set_progress(percent);
+            let _ = on_progress.send(DownloadProgressEvent { percent });
+        }),
+        &|errors| {
+            let _ = on_error.send(DownloadErrorEvent { errors });
+        },
+    )
+    .await;
+
+    batch.finish(&result);
+
+    result
+        .inspect_err(|e| error!("sync failed: {e}"))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn get_sync_history(app: AppHandle) -> Result<Vec<SyncRun>, String> {
+    info!("get_sync_history command invoked");
+
+    let app_data = app
+        .path()
+        .app_data_dir()
+        .inspect_err(|e| error!("error reading app data dir: {e}"))
+        .context("reading app data dir")
+        .map_err(|e| e.to_string())?;
+
+    schedule::load_history(&app_data)
+        .await
+        .inspect_err(|e| error!("failed loading sync history: {e}"))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn list_library(
+    app: AppHandle,
+    subject: Option<String>,
+) -> Result<Vec<LibraryEntry>, String> {
+    info!("list_library command invoked");
+
+    let app_data = app
+        .path()
+        .app_data_dir()
+        .inspect_err(|e| error!("error reading app data dir: {e}"))
+        .context("reading app data dir")
+        .map_err(|e| e.to_string())?;
+
+    let mut entries = library::load(&app_data)
+        .await
+        .inspect_err(|e| error!("failed loading library: {e}"))
+        .map_err(|e| e.to_string())?;
+
+    if let Some(subject) = subject {
+        entries.retain(|entry| entry.subject == subject);
+    }
+
+    entries.sort_by(|a, b| a.subject.cmp(&b.subject).then(a.number.cmp(&b.number)));
+
+    Ok(entries)
+}
+
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn search_library(app: AppHandle, query: String) -> Result<Vec<LibraryEntry>, String> {
+    info!("search_library command invoked");
+
+    let app_data = app
+        .path()
+        .app_data_dir()
+        .inspect_err(|e| error!("error reading app data dir: {e}"))
+        .context("reading app data dir")
+        .map_err(|e| e.to_string())?;
+
+    let entries = library::load(&app_data)
+        .await
+        .inspect_err(|e| error!("failed loading library: {e}"))
+        .map_err(|e| e.to_string())?;
+
+    Ok(library::search(entries, &query))
+}
+
+/// Of the given ttids, returns those that have been downloaded and are still in the library
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn get_downloaded(app: AppHandle, ttids: Vec<i32>) -> Result<Vec<i32>, String> {
+    let app_data = app
+        .path()
+        .app_data_dir()
+        .inspect_err(|e| error!("error reading app data dir: {e}"))
+        .context("reading app data dir")
+        .map_err(|e| e.to_string())?;
+
+    let entries = library::load(&app_data)
+        .await
+        .inspect_err(|e| error!("failed loading library: {e}"))
+        .map_err(|e| e.to_string())?;
+
+    let mut downloaded = vec![];
+    for entry in entries {
+        if ttids.contains(&entry.ttid)
+            && !downloaded.contains(&entry.ttid)
+            && tokio::fs::try_exists(&entry.path).await.unwrap_or(false)
+        {
+            downloaded.push(entry.ttid);
+        }
+    }
+
+    Ok(downloaded)
+}
+
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn verify_library(app: AppHandle) -> Result<Vec<VerifiedEntry>, String> {
+    info!("verify_library command invoked");
+
+    let app_data = app
+        .path()
+        .app_data_dir()
+        .inspect_err(|e| error!("error reading app data dir: {e}"))
+        .context("reading app data dir")
+        .map_err(|e| e.to_string())?;
+
+    library::verify(&app, &app_data)
+        .await
+        .inspect_err(|e| error!("failed verifying library: {e}"))
+        .map_err(|e| e.to_string())
+}
+
+/// Points a library entry at a file that was moved elsewhere
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn relink_library_entry(
+    app: AppHandle,
+    path: String,
+    new_path: String,
+) -> Result<LibraryEntry, String> {
+    info!("relink_library_entry command invoked");
+
+    let app_data = app
+        .path()
+        .app_data_dir()
+        .inspect_err(|e| error!("error reading app data dir: {e}"))
+        .context("reading app data dir")
+        .map_err(|e| e.to_string())?;
+
+    library::relink(&app_data, &path, Path::new(&new_path))
+        .await
+        .inspect_err(|e| error!("failed relinking library entry: {e}"))
+        .map_err(|e| e.to_string())
+}
+
+/// Throws away the given library entries' files and downloads them again into the same folders
+#[tauri::command]
+#[instrument(skip_all)]
+#[allow(clippy::too_many_arguments)]
+pub async fn redownload_library_entries(
+    downloads: State<'_, Arc<DownloadManager>>,
+    token_store: State<'_, Arc<Token>>,
+    app: AppHandle,
+    token: String,
+    paths: Vec<String>,
+    on_start: Channel<BatchStartedEvent>,
+    on_progress: Channel<DownloadProgressEvent>,
+    on_error: Channel<DownloadErrorEvent>,
+) -> Result<Vec<i32>, String> {
+    info!("redownload_library_entries command invoked");
+
+    let app_data = app
+        .path()
+        .app_data_dir()
+        .inspect_err(|e| error!("error reading app data dir: {e}"))
+        .context("reading app data dir")
+        .map_err(|e| e.to_string())?;
+
+    let entries = library::find(&app_data, &paths)
+        .await
+        .inspect_err(|e| error!("failed reading library entries: {e}"))
+        .map_err(|e| e.to_string())?;
+
+    // Entries are saved in their subject's folder, which `subject_folder` leaves as is
+    let mut folders: Vec<(String, Vec<Video>)> = vec![];
+    for entry in entries {
+        let folder = Path::new(&entry.path)
+            .parent()
+            .map(|folder| folder.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        // Downloaded over the old file, which stays in place until the new one has finished
+        let video = Video {
+            ttid: entry.ttid,
+            topic: entry.topic,
+            subject_name: entry.subject,
+            number: entry.number,
+            start_time: entry.date,
+            replace: Some(PathBuf::from(entry.path)),
+        };
+
+        match folders.iter_mut().find(|(existing, _)| *existing == folder) {
+            Some((_, videos)) => videos.push(video),
+            None => folders.push((folder, vec![video])),
+        }
+    }
+
+    let lectures = folders.iter().map(|(_, videos)| videos.len()).sum();
+    let batch = downloads.start(&app, BatchKind::Redownload, Some(lectures));
+    let _ = on_start.send(BatchStartedEvent { id: batch.id() });
+
+    token_store.set(token);
+
+    let on_progress = Arc::new(on_progress);
+    let num_folders = folders.len();
+
+    let result = async {
+        let mut downloaded = vec![];
+
+        for (i, (folder, videos)) in folders.into_iter().enumerate() {
+            let on_progress = Arc::clone(&on_progress);
+            let progress_batch = batch.clone();
+            downloaded.extend(
+                download_videos(
+                    app.clone(),
+                    Arc::clone(&token_store),
+                    folder,
+                    videos,
+                    batch.clone(),
+                    move |percent| {
+                        let percent = (i as f32 * 100.0 + percent) / num_folders as f32;
+                        progress_batch.set_progress(percent);
+                        let _ = on_progress.send(DownloadProgressEvent { percent });
+                    },
+                    |errors| {
+                        let _ = on_error.send(DownloadErrorEvent { errors });
+                    },
+                )
+                .await?,
+            );
+        }
+
+        Ok::<_, String>(downloaded)
+    }
+    .await;
+
+    batch.finish(&result);
+    result
+}
+
+/// Writes playlists and an index page of a subject's downloaded lectures into the folder
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn export_library(
+    app: AppHandle,
+    subject: String,
+    folder: String,
+) -> Result<Vec<String>, String> {
+    info!("export_library command invoked");
+
+    let app_data = app
+        .path()
+        .app_data_dir()
+        .inspect_err(|e| error!("error reading app data dir: {e}"))
+        .context("reading app data dir")
+        .map_err(|e| e.to_string())?;
+
+    let entries = library::load(&app_data)
+        .await
+        .inspect_err(|e| error!("failed loading library: {e}"))
+        .map_err(|e| e.to_string())?;
+
+    let written = library::export::export(entries, &subject, Path::new(&folder))
+        .await
+        .inspect_err(|e| error!("failed exporting library: {e}"))
+        .map_err(|e| e.to_string())?;
+
+    Ok(written
+        .into_iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect())
+}
+
+/// Estimates how much space downloading the videos into the folder will take, and whether
+/// there is enough of it
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn check_disk_space(
+    token_store: State<'_, Arc<Token>>,
+    app: AppHandle,
+    token: String,
+    folder: String,
+    videos: Vec<Video>,
+) -> Result<disk::DiskSpaceReport, String> {
+    info!("check_disk_space command invoked");
+
+    token_store.set(token);
+
+    let settings = Arc::new(get_resolved_settings(&app).await);
+    let mut set = JoinSet::new();
+
+    for video in videos {
+        // Already downloaded videos are skipped
+        if tokio::fs::try_exists(output_location(&settings, &video, &folder))
+            .await
+            .unwrap_or(false)
+        {
+            continue;
+        }
+
+        let (settings, token) = (Arc::clone(&settings), Arc::clone(&token_store));
+        set.spawn(async move {
+            let estimate = downloader::estimate_size(&settings, &token, video.ttid as usize).await;
+            (video.ttid, estimate)
+        });
+    }
+
+    let (mut temp_required, mut destination_required, mut unknown) = (0, 0, vec![]);
+
+    while let Some(res) = set.join_next().await {
+        match res.map_err(|e| e.to_string())? {
+            (_, Ok(estimate)) => {
+                temp_required += estimate.total - estimate.cached;
+                destination_required += estimate.total;
+            }
+            (ttid, Err(e)) => {
+                warn!("Failed to estimate size of lecture {ttid}: {e}");
+                unknown.push(ttid);
+            }
+        }
+    }
+
+    let temp = get_temp(&settings);
+    let report = tokio::task::spawn_blocking(move || {
+        disk::DiskSpaceReport::new(
+            &temp,
+            Path::new(&folder),
+            temp_required,
+            destination_required,
+            unknown,
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .inspect_err(|e| error!("failed checking disk space: {e}"))
+    .map_err(|e| e.to_string())?;
+
+    info!("Disk space preflight: {report:?}");
+
+    Ok(report)
+}
+
+/// Moves the cache to a new folder, or back to the system's temp directory, and saves it in
+/// the settings. Returns the number of lectures that were moved
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn move_cache(app: AppHandle, cache_dir: Option<String>) -> Result<usize, String> {
+    info!("move_cache command invoked");
+
+    let app_data = app
+        .path()
+        .app_data_dir()
+        .inspect_err(|e| error!("error reading app data dir: {e}"))
+        .context("reading app data dir")
+        .map_err(|e| e.to_string())?;
+
+    let mut settings = get_resolved_settings(&app).await;
+    let from = get_temp(&settings);
+
+    settings.cache_dir = cache_dir;
+    let to = get_temp(&settings);
+
+    if from == to {
+        return Ok(0);
+    }
+
+    let moved = cache::migrate(from, to)
+        .await
+        .inspect_err(|e| error!("failed migrating cache: {e}"))
+        .map_err(|e| e.to_string())?;
+
+    write_settings(&app_data, &settings).await?;
+
+    info!("Moved {moved} lectures to the new cache");
+
+    Ok(moved)
+}
+
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn list_cache(app: AppHandle) -> Result<Vec<cache::CacheEntry>, String> {
+    info!("list_cache command invoked");
+
+    cache::list(get_temp(&get_resolved_settings(&app).await))
+        .await
+        .inspect_err(|e| error!("failed listing cache: {e}"))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn remove_cache_entry(app: AppHandle, ttid: i32) -> Result<(), String> {
+    info!("remove_cache_entry command invoked");
+
+    cache::remove(&get_temp(&get_resolved_settings(&app).await), ttid)
+        .await
+        .inspect_err(|e| error!("failed removing cache entry: {e}"))
+        .map_err(|e| e.to_string())
+}
+
+/// Muxes a lecture again from its archived bundle, into any format ffmpeg can write to
+#[tauri::command]
+#[instrument(skip_all)]
+pub async fn remux_bundle(app: AppHandle, bundle: String, output: String) -> Result<(), String> {
+    info!("remux_bundle command invoked");
+
+    let temp = get_temp(&get_resolved_settings(&app).await);
+
+    archive::remux(&app, Path::new(&bundle), &temp, Path::new(&output))
+        .await
+        .inspect_err(|e| error!("failed remuxing bundle: {e}"))
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,364 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::prelude::*;
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{watch, Mutex},
+};
+
+use super::downloader::client;
+
+/// How long to wait for the frontend to hand over a fresh token
+const REFRESH_TIMEOUT: Duration = Duration::new(60, 0);
+
+const LOGTO_ENDPOINT: &str = dotenvy_macro::dotenv!("VITE_LOGTO_ENDPOINT");
+const LOGTO_APP_ID: &str = dotenvy_macro::dotenv!("VITE_LOGTO_APP_ID");
+
+const KEYRING_SERVICE: &str = "multipartus-downloader";
+const KEYRING_USER: &str = "refresh-token";
+
+/// Where the backend keeps the refresh token it uses to log in without the webview
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CredentialStore {
+    /// Only the frontend holds credentials
+    #[default]
+    None,
+    /// Secret Service on Linux, Keychain on macOS, and Credential Manager on Windows
+    Keyring,
+    /// An AES-GCM encrypted file in the app data dir, with its key in a file beside it that only
+    /// the current user can read. For systems without a keyring
+    EncryptedFile,
+}
+
+impl CredentialStore {
+    #[instrument(skip(dir))]
+    pub async fn load(self, dir: PathBuf) -> Result<Option<String>> {
+        match self {
+            Self::None => Ok(None),
+
+            Self::Keyring => tokio::task::spawn_blocking(|| {
+                match keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)?.get_password() {
+                    Ok(refresh_token) => Ok(Some(refresh_token)),
+                    Err(keyring::Error::NoEntry) => Ok(None),
+                    Err(err) => Err(err),
+                }
+            })
+            .await
+            .context("Keyring task failed!")?
+            .context("Failed to read credentials from the keyring!"),
+
+            Self::EncryptedFile => {
+                let file = dir.join("credentials.bin");
+                if !tokio::fs::try_exists(&file).await.unwrap_or(false) {
+                    return Ok(None);
+                }
+
+                let cipher = Aes256Gcm::new(&file_key(&dir).await?);
+
+                let contents = tokio::fs::read(&file)
+                    .await
+                    .context("Failed to read credentials file!")?;
+
+                if contents.len() < 12 {
+                    return Err(anyhow::Error::msg("Credentials file is corrupted!"));
+                }
+
+                let (nonce, ciphertext) = contents.split_at(12);
+                let refresh_token = cipher
+                    .decrypt(Nonce::from_slice(nonce), ciphertext)
+                    .map_err(|_| anyhow::Error::msg("Failed to decrypt credentials file!"))?;
+
+                Ok(Some(
+                    String::from_utf8(refresh_token).context("Credentials file is corrupted!")?,
+                ))
+            }
+        }
+    }
+
+    #[instrument(skip(dir, refresh_token))]
+    pub async fn save(self, dir: PathBuf, refresh_token: String) -> Result<()> {
+        match self {
+            Self::None => Ok(()),
+
+            Self::Keyring => tokio::task::spawn_blocking(move || {
+                keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)?.set_password(&refresh_token)
+            })
+            .await
+            .context("Keyring task failed!")?
+            .context("Failed to save credentials to the keyring!"),
+
+            Self::EncryptedFile => {
+                let cipher = Aes256Gcm::new(&file_key(&dir).await?);
+                let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+                let ciphertext = cipher
+                    .encrypt(&nonce, refresh_token.as_bytes())
+                    .map_err(|_| anyhow::Error::msg("Failed to encrypt credentials!"))?;
+
+                let mut contents = nonce.to_vec();
+                contents.extend(ciphertext);
+
+                tokio::fs::create_dir_all(&dir)
+                    .await
+                    .context("Failed to create app data dir!")?;
+
+                write_private(&dir.join("credentials.bin"), &contents)
+                    .await
+                    .context("Failed to write credentials file!")
+            }
+        }
+    }
+
+    /// Removes whatever credentials this store holds
+    #[instrument(skip(dir))]
+    pub async fn clear(self, dir: PathBuf) -> Result<()> {
+        match self {
+            Self::None => Ok(()),
+
+            Self::Keyring => tokio::task::spawn_blocking(|| {
+                match keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)
+                    .and_then(|entry| entry.delete_credential())
+                {
+                    Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+                    // Nothing can have been stored where there is no keyring
+                    Err(
+                        err @ (keyring::Error::NoStorageAccess(_)
+                        | keyring::Error::PlatformFailure(_)),
+                    ) => {
+                        warn!("Keyring is unavailable, so there is nothing to clear: {err}");
+                        Ok(())
+                    }
+                    Err(err) => Err(err),
+                }
+            })
+            .await
+            .context("Keyring task failed!")?
+            .context("Failed to remove credentials from the keyring!"),
+
+            Self::EncryptedFile => {
+                for file in ["credentials.bin", "credentials.key"] {
+                    if let Err(err) = tokio::fs::remove_file(dir.join(file)).await {
+                        if err.kind() != std::io::ErrorKind::NotFound {
+                            return Err(err).context(format!("Failed to remove `{file}`!"));
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Reads the key for the credentials file from `credentials.key`, generating it on first use
+async fn file_key(dir: &Path) -> Result<Key<Aes256Gcm>> {
+    let path = dir.join("credentials.key");
+
+    match tokio::fs::read(&path).await {
+        Ok(key) if key.len() == 32 => {
+            // Keys written by earlier versions may be readable by other users
+            restrict_permissions(&path).await?;
+            return Ok(*Key::<Aes256Gcm>::from_slice(&key));
+        }
+        Ok(_) => warn!("Credentials key is corrupted. Generating a new one"),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+        Err(err) => return Err(err).context("Failed to read the credentials key!"),
+    }
+
+    tokio::fs::create_dir_all(dir)
+        .await
+        .context("Failed to create app data dir!")?;
+
+    let key = Aes256Gcm::generate_key(OsRng);
+    write_private(&path, key.as_slice())
+        .await
+        .context("Failed to write the credentials key!")?;
+
+    Ok(key)
+}
+
+/// Writes a file that only the current user can read, from the moment it is created
+async fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options
+        .open(path)
+        .await
+        .context(format!("Failed to open `{}`!", path.display()))?;
+    file.write_all(contents)
+        .await
+        .context(format!("Failed to write `{}`!", path.display()))?;
+    file.flush()
+        .await
+        .context(format!("Failed to flush `{}`!", path.display()))?;
+
+    // The mode only applies to new files, and an older one may have been created without it
+    restrict_permissions(path).await
+}
+
+/// Keeps a file readable only by the current user
+async fn restrict_permissions(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+            .await
+            .context(format!(
+                "Failed to restrict permissions of `{}`!",
+                path.display()
+            ))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+struct TokenResponse {
+    id_token: String,
+    refresh_token: Option<String>,
+}
+
+/// A refresh token held by the backend, which lets it log in without the webview
+pub struct Credentials {
+    pub store: CredentialStore,
+    pub dir: PathBuf,
+    pub refresh_token: String,
+}
+
+impl Credentials {
+    /// Exchanges the refresh token for a new ID token through the OAuth token endpoint
+    async fn refresh(&mut self) -> Result<String> {
+        let res = client()
+            .post(format!(
+                "{}/oidc/token",
+                LOGTO_ENDPOINT.trim_end_matches('/')
+            ))
+            .timeout(Duration::new(30, 0))
+            .form(&[
+                ("grant_type", "refresh_token"),
+                ("client_id", LOGTO_APP_ID),
+                ("refresh_token", &self.refresh_token),
+            ])
+            .send()
+            .await
+            .context("Failed to connect to the login server!")?
+            .error_for_status()
+            .context("Login server refused to refresh the token!")?
+            .bytes()
+            .await
+            .context("Failed to read token response!")?;
+
+        let TokenResponse {
+            id_token,
+            refresh_token,
+        } = serde_json::from_slice(&res).context("Failed to parse token response!")?;
+
+        // Refresh tokens are rotated, so the new one has to be saved for the next refresh
+        if let Some(refresh_token) = refresh_token {
+            self.refresh_token = refresh_token;
+            self.store
+                .save(self.dir.clone(), self.refresh_token.clone())
+                .await
+                .inspect_err(|e| error!("Failed to save rotated refresh token: {e}"))
+                .unwrap_or(());
+        }
+
+        Ok(id_token)
+    }
+}
+
+/// The ID token used to authenticate requests, which can be swapped out while downloads are running
+pub struct Token {
+    value: watch::Sender<String>,
+    /// Asks for a fresh token, which is expected to arrive through [`Token::set`]
+    request_refresh: Box<dyn Fn() + Send + Sync>,
+    /// Held while a refresh is in flight, so concurrent requests that get rejected share one refresh
+    credentials: Mutex<Option<Credentials>>,
+}
+
+impl Token {
+    pub fn new(request_refresh: impl Fn() + Send + Sync + 'static) -> Self {
+        Self {
+            value: watch::Sender::new(String::new()),
+            request_refresh: Box::new(request_refresh),
+            credentials: Mutex::new(None),
+        }
+    }
+
+    pub fn get(&self) -> String {
+        self.value.borrow().clone()
+    }
+
+    pub fn set(&self, value: String) {
+        self.value.send_replace(value);
+    }
+
+    pub async fn set_credentials(&self, credentials: Option<Credentials>) {
+        *self.credentials.lock().await = credentials;
+    }
+
+    /// Moves the held credentials into another store, or drops them when that store keeps none
+    pub async fn switch_store(&self, store: CredentialStore) -> Result<()> {
+        let mut credentials = self.credentials.lock().await;
+
+        if store == CredentialStore::None {
+            *credentials = None;
+        } else if let Some(credentials) = credentials.as_mut() {
+            store
+                .save(credentials.dir.clone(), credentials.refresh_token.clone())
+                .await?;
+            credentials.store = store;
+        }
+
+        Ok(())
+    }
+
+    /// Gets a fresh token with the stored credentials if there are any. Otherwise, requests one
+    /// and waits until a token that differs from the rejected `stale` token arrives
+    pub async fn refresh(&self, stale: &str) -> Result<String> {
+        let mut credentials = self.credentials.lock().await;
+
+        let mut rx = self.value.subscribe();
+
+        // Another request may have refreshed the token while this one was waiting
+        if *rx.borrow_and_update() != stale {
+            return Ok(self.get());
+        }
+
+        if let Some(credentials) = credentials.as_mut() {
+            match credentials.refresh().await {
+                Ok(fresh) => {
+                    info!("Refreshed token with stored credentials");
+                    self.set(fresh.clone());
+                    return Ok(fresh);
+                }
+                Err(err) => warn!("Failed to refresh token with stored credentials: {err}"),
+            }
+        }
+
+        info!("Requesting a fresh token");
+        (self.request_refresh)();
+
+        let fresh = tokio::time::timeout(REFRESH_TIMEOUT, rx.wait_for(|value| value != stale))
+            .await
+            .context("Timed out waiting for a fresh login token! Try logging in again.")?
+            .context("Token store was dropped while waiting for a fresh login token!")?
+            .clone();
+
+        info!("Received a fresh token");
+
+        Ok(fresh)
+    }
+}
@@ -0,0 +1,549 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::prelude::*;
+
+use chrono::{DateTime, Local};
+use tauri::{AppHandle, Emitter};
+use tokio_util::sync::CancellationToken;
+
+pub type BatchId = u64;
+
+/// Finished batches kept for listing, beyond which the oldest are dropped
+const MAX_FINISHED: usize = 20;
+
+/// What started a batch
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BatchKind {
+    Download,
+    Sync,
+    Redownload,
+    BackgroundSync,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BatchStatus {
+    Running,
+    /// Cancelled, but its lectures are still being stopped and cleaned up
+    Cancelling,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl BatchStatus {
+    pub fn is_finished(self) -> bool {
+        matches!(self, Self::Completed | Self::Failed | Self::Cancelled)
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchInfo {
+    pub id: BatchId,
+    pub kind: BatchKind,
+    /// Unknown for syncs, which find their lectures as they go
+    pub lectures: Option<usize>,
+    pub status: BatchStatus,
+    /// From 0 to 100
+    pub progress: f32,
+    pub started_at: DateTime<Local>,
+    /// Lectures waiting for their turn, in the order they'll be downloaded
+    pub queue: Vec<QueuedLecture>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedLecture {
+    pub ttid: i32,
+    pub number: i32,
+    pub topic: String,
+    /// Higher goes first, with ties going in the order they were queued
+    pub priority: i64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BatchFinishedEvent {
+    id: BatchId,
+    status: BatchStatus,
+}
+
+struct Batch {
+    info: BatchInfo,
+    cancel: CancellationToken,
+    /// Every lecture queued in the batch, including those taken off the queue since
+    lectures: Vec<i32>,
+}
+
+/// Keeps track of every batch of downloads that is running, so they can be listed and cancelled
+/// independently of each other
+#[derive(Default)]
+pub struct DownloadManager {
+    next_id: AtomicU64,
+    // Progress is reported from synchronous callbacks, and batches are finished on drop
+    batches: Mutex<BTreeMap<BatchId, Batch>>,
+}
+
+impl DownloadManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a new batch, which runs until the returned handle is dropped
+    pub fn start(
+        self: &Arc<Self>,
+        app: &AppHandle,
+        kind: BatchKind,
+        lectures: Option<usize>,
+    ) -> BatchHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let cancel = CancellationToken::new();
+
+        let info = BatchInfo {
+            id,
+            kind,
+            lectures,
+            status: BatchStatus::Running,
+            progress: 0.0,
+            started_at: Local::now(),
+            queue: vec![],
+        };
+
+        info!("Starting batch {id} ({kind:?})");
+
+        self.batches.lock().unwrap().insert(
+            id,
+            Batch {
+                info,
+                cancel: cancel.clone(),
+                lectures: vec![],
+            },
+        );
+
+        BatchHandle(Arc::new(HandleInner {
+            id,
+            cancel,
+            manager: Arc::clone(self),
+            app: app.clone(),
+            outcome: Mutex::new(None),
+        }))
+    }
+
+    /// Every running batch, and those that finished since the last time batches were listed.
+    /// Finished batches are only listed once, with their final status
+    pub fn list(&self) -> Vec<BatchInfo> {
+        let mut batches = self.batches.lock().unwrap();
+        let listed = batches.values().map(|batch| batch.info.clone()).collect();
+
+        batches.retain(|_, batch| !batch.info.status.is_finished());
+        listed
+    }
+
+    /// Records the final status of a batch, keeping it around until it's been listed
+    fn finish(&self, id: BatchId, status: BatchStatus) {
+        let mut batches = self.batches.lock().unwrap();
+
+        if let Some(batch) = batches.get_mut(&id) {
+            batch.info.status = status;
+            batch.info.queue.clear();
+            // Its lectures are done with, so their cache can be evicted and other batches take them
+            batch.lectures.clear();
+        }
+
+        // Nobody may be listing batches, so only the latest few finished ones are kept
+        let finished = batches
+            .iter()
+            .filter(|(_, batch)| batch.info.status.is_finished())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in finished.iter().rev().skip(MAX_FINISHED) {
+            batches.remove(id);
+        }
+    }
+
+    /// Adds lectures to the batch's queue, refusing and returning those another batch has
+    fn enqueue(
+        &self,
+        id: BatchId,
+        lectures: impl IntoIterator<Item = QueuedLecture>,
+    ) -> Vec<QueuedLecture> {
+        let mut batches = self.batches.lock().unwrap();
+
+        let in_use = batches
+            .iter()
+            .filter(|(other, _)| **other != id)
+            .flat_map(|(_, batch)| batch.lectures.iter().copied())
+            .collect::<HashSet<_>>();
+        let (refused, lectures): (Vec<_>, Vec<_>) = lectures
+            .into_iter()
+            .partition(|lecture| in_use.contains(&lecture.ttid));
+
+        if let Some(batch) = batches.get_mut(&id) {
+            batch
+                .lectures
+                .extend(lectures.iter().map(|lecture| lecture.ttid));
+            batch.info.queue.extend(lectures);
+            sort_queue(&mut batch.info.queue);
+        }
+
+        refused
+    }
+
+    /// Lectures queued in any running batch, whose cached chunks are still needed
+    pub fn lectures_in_use(&self) -> Vec<i32> {
+        self.batches
+            .lock()
+            .unwrap()
+            .values()
+            .flat_map(|batch| batch.lectures.iter().copied())
+            .collect()
+    }
+
+    /// Cancels the batch. Returns whether it was running
+    pub fn cancel(&self, id: BatchId) -> bool {
+        let mut batches = self.batches.lock().unwrap();
+        let Some(batch) = batches
+            .get_mut(&id)
+            .filter(|batch| !batch.info.status.is_finished())
+        else {
+            return false;
+        };
+
+        info!("Cancelling batch {id}");
+        batch.cancel.cancel();
+        batch.info.status = BatchStatus::Cancelling;
+        true
+    }
+
+    fn update<T>(&self, id: BatchId, f: impl FnOnce(&mut BatchInfo) -> T) -> Option<T> {
+        self.batches
+            .lock()
+            .unwrap()
+            .get_mut(&id)
+            .map(|batch| f(&mut batch.info))
+    }
+
+    /// Changes the priority of a queued lecture, given the priorities of the whole queue
+    fn reprioritize(
+        &self,
+        id: BatchId,
+        ttid: i32,
+        priority: impl FnOnce(&[QueuedLecture]) -> i64,
+    ) -> Result<()> {
+        self.update(id, |info| {
+            let priority = priority(&info.queue);
+            let lecture = info
+                .queue
+                .iter_mut()
+                .find(|lecture| lecture.ttid == ttid)
+                .ok_or_else(|| {
+                    anyhow::Error::msg(format!("Lecture {ttid} is not queued in batch {id}!"))
+                })?;
+            lecture.priority = priority;
+            sort_queue(&mut info.queue);
+            Ok(())
+        })
+        .unwrap_or_else(|| Err(anyhow::Error::msg(format!("No batch {id} is running!"))))
+    }
+
+    /// Moves a queued lecture to the front of the queue
+    pub fn bump(&self, id: BatchId, ttid: i32) -> Result<()> {
+        self.reprioritize(id, ttid, |queue| {
+            queue.iter().map(|l| l.priority).max().unwrap_or(0) + 1
+        })
+    }
+
+    /// Moves a queued lecture to the back of the queue
+    pub fn defer(&self, id: BatchId, ttid: i32) -> Result<()> {
+        self.reprioritize(id, ttid, |queue| {
+            queue.iter().map(|l| l.priority).min().unwrap_or(0) - 1
+        })
+    }
+
+    /// Puts the given lectures at the front of the queue, in that order. Lectures that aren't
+    /// given keep their place behind them
+    pub fn reorder(&self, id: BatchId, ttids: &[i32]) -> Result<()> {
+        self.update(id, |info| {
+            let top = info.queue.iter().map(|l| l.priority).max().unwrap_or(0);
+            let count = ttids.len() as i64;
+
+            for lecture in info.queue.iter_mut() {
+                if let Some(i) = ttids.iter().position(|&ttid| ttid == lecture.ttid) {
+                    lecture.priority = top + count - i as i64;
+                }
+            }
+            sort_queue(&mut info.queue);
+        })
+        .ok_or_else(|| anyhow::Error::msg(format!("No batch {id} is running!")))
+    }
+}
+
+/// Highest priority first. The sort is stable, so ties stay in the order they were queued
+fn sort_queue(queue: &mut [QueuedLecture]) {
+    queue.sort_by_key(|lecture| Reverse(lecture.priority));
+}
+
+struct HandleInner {
+    id: BatchId,
+    cancel: CancellationToken,
+    manager: Arc<DownloadManager>,
+    app: AppHandle,
+    outcome: Mutex<Option<bool>>,
+}
+
+/// A running batch. Cloning it shares the batch, which finishes once every clone is dropped
+#[derive(Clone)]
+pub struct BatchHandle(Arc<HandleInner>);
+
+impl BatchHandle {
+    pub fn id(&self) -> BatchId {
+        self.0.id
+    }
+
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.0.cancel.clone()
+    }
+
+    pub fn set_progress(&self, progress: f32) {
+        self.0
+            .manager
+            .update(self.0.id, |info| info.progress = progress);
+    }
+
+    /// Adds lectures to the queue, behind those of the same priority. Lectures another running
+    /// batch already has are left out and returned, since both would write to the same files
+    pub fn enqueue(&self, lectures: impl IntoIterator<Item = QueuedLecture>) -> Vec<QueuedLecture> {
+        self.0.manager.enqueue(self.0.id, lectures)
+    }
+
+    /// Lectures queued in any running batch, this one included
+    pub fn lectures_in_use(&self) -> Vec<i32> {
+        self.0.manager.lectures_in_use()
+    }
+
+    /// Takes the lecture that should be downloaded next off the queue
+    pub fn next_queued(&self) -> Option<QueuedLecture> {
+        self.0
+            .manager
+            .update(self.0.id, |info| {
+                (!info.queue.is_empty()).then(|| info.queue.remove(0))
+            })
+            .flatten()
+    }
+
+    /// Records whether the batch succeeded, which is reported once it's dropped
+    pub fn finish<T, E>(&self, result: &std::result::Result<T, E>) {
+        *self.0.outcome.lock().unwrap() = Some(result.is_ok());
+    }
+}
+
+impl Drop for HandleInner {
+    fn drop(&mut self) {
+        let status = if self.cancel.is_cancelled() {
+            BatchStatus::Cancelled
+        } else {
+            match *self.outcome.lock().unwrap() {
+                Some(true) => BatchStatus::Completed,
+                _ => BatchStatus::Failed,
+            }
+        };
+
+        info!("Batch {} finished: {status:?}", self.id);
+        self.manager.finish(self.id, status);
+
+        let _ = self.app.emit(
+            "batch-finished",
+            BatchFinishedEvent {
+                id: self.id,
+                status,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lecture(ttid: i32, priority: i64) -> QueuedLecture {
+        QueuedLecture {
+            ttid,
+            number: ttid,
+            topic: format!("Lecture {ttid}"),
+            priority,
+        }
+    }
+
+    /// A manager with one running batch, whose queue holds the given lectures in that order
+    fn manager(ttids: &[i32]) -> DownloadManager {
+        let manager = DownloadManager::new();
+        manager.batches.lock().unwrap().insert(
+            0,
+            Batch {
+                info: BatchInfo {
+                    id: 0,
+                    kind: BatchKind::Download,
+                    lectures: Some(ttids.len()),
+                    status: BatchStatus::Running,
+                    progress: 0.0,
+                    started_at: Local::now(),
+                    queue: ttids.iter().map(|&ttid| lecture(ttid, 0)).collect(),
+                },
+                cancel: CancellationToken::new(),
+                lectures: ttids.to_vec(),
+            },
+        );
+        manager
+    }
+
+    fn queue(manager: &DownloadManager) -> Vec<i32> {
+        manager.list()[0]
+            .queue
+            .iter()
+            .map(|lecture| lecture.ttid)
+            .collect()
+    }
+
+    #[test]
+    fn sorts_by_priority_keeping_ties_in_order() {
+        let mut queue = vec![lecture(1, 0), lecture(2, 5), lecture(3, 0), lecture(4, -1)];
+        sort_queue(&mut queue);
+
+        let order = queue.iter().map(|lecture| lecture.ttid).collect::<Vec<_>>();
+        assert_eq!(order, [2, 1, 3, 4]);
+    }
+
+    #[test]
+    fn bumps_to_the_front() {
+        let manager = manager(&[1, 2, 3]);
+
+        manager.bump(0, 3).unwrap();
+        assert_eq!(queue(&manager), [3, 1, 2]);
+
+        manager.bump(0, 2).unwrap();
+        assert_eq!(queue(&manager), [2, 3, 1]);
+    }
+
+    #[test]
+    fn defers_to_the_back() {
+        let manager = manager(&[1, 2, 3]);
+
+        manager.defer(0, 1).unwrap();
+        assert_eq!(queue(&manager), [2, 3, 1]);
+
+        manager.defer(0, 2).unwrap();
+        assert_eq!(queue(&manager), [3, 1, 2]);
+    }
+
+    #[test]
+    fn reorders_given_lectures_ahead_of_the_rest() {
+        let manager = manager(&[1, 2, 3, 4, 5]);
+
+        manager.reorder(0, &[4, 2]).unwrap();
+        assert_eq!(queue(&manager), [4, 2, 1, 3, 5]);
+
+        // Reordering again still puts them ahead of earlier ones
+        manager.reorder(0, &[5]).unwrap();
+        assert_eq!(queue(&manager), [5, 4, 2, 1, 3]);
+    }
+
+    #[test]
+    fn rejects_unknown_lectures_and_batches() {
+        let manager = manager(&[1, 2]);
+
+        assert!(manager.bump(0, 3).is_err());
+        assert!(manager.defer(1, 1).is_err());
+        assert!(manager.reorder(1, &[1]).is_err());
+        assert_eq!(queue(&manager), [1, 2]);
+    }
+
+    #[test]
+    fn cancels_only_the_given_batch() {
+        let manager = manager(&[1]);
+
+        assert!(!manager.cancel(1));
+        assert_eq!(manager.list()[0].status, BatchStatus::Running);
+
+        assert!(manager.cancel(0));
+        assert_eq!(manager.list()[0].status, BatchStatus::Cancelling);
+    }
+
+    #[test]
+    fn refuses_lectures_another_batch_has() {
+        let manager = manager(&[1, 2]);
+        let mut info = manager.list()[0].clone();
+        info.id = 1;
+        info.queue.clear();
+        manager.batches.lock().unwrap().insert(
+            1,
+            Batch {
+                info,
+                cancel: CancellationToken::new(),
+                lectures: vec![],
+            },
+        );
+
+        let refused = manager.enqueue(1, [lecture(2, 0), lecture(3, 0)]);
+        assert_eq!(
+            refused
+                .iter()
+                .map(|lecture| lecture.ttid)
+                .collect::<Vec<_>>(),
+            [2]
+        );
+        assert_eq!(manager.list()[1].queue.len(), 1);
+        assert_eq!(manager.list()[1].queue[0].ttid, 3);
+
+        // A batch can queue its own lectures again
+        assert!(manager.enqueue(0, [lecture(1, 0)]).is_empty());
+    }
+
+    #[test]
+    fn lists_finished_batches_once() {
+        let manager = manager(&[1, 2]);
+        manager.finish(0, BatchStatus::Failed);
+
+        assert!(manager.lectures_in_use().is_empty());
+        assert!(!manager.cancel(0));
+
+        let listed = manager.list();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].status, BatchStatus::Failed);
+        assert!(listed[0].queue.is_empty());
+
+        assert!(manager.list().is_empty());
+    }
+
+    #[test]
+    fn keeps_only_the_latest_finished_batches() {
+        let manager = manager(&[]);
+        let running = manager.list()[0].clone();
+        for id in 1..=MAX_FINISHED as BatchId + 5 {
+            let mut info = running.clone();
+            info.id = id;
+            manager.batches.lock().unwrap().insert(
+                id,
+                Batch {
+                    info,
+                    cancel: CancellationToken::new(),
+                    lectures: vec![],
+                },
+            );
+            manager.finish(id, BatchStatus::Completed);
+        }
+
+        let listed = manager.list();
+        // The running batch, and the latest finished ones
+        assert_eq!(listed.len(), MAX_FINISHED + 1);
+        assert_eq!(listed[0].status, BatchStatus::Running);
+        assert_eq!(listed[1].id, 6);
+    }
+}
@@ -0,0 +1,268 @@
+use std::path::{Path, PathBuf};
+
+use crate::prelude::*;
+
+use chrono::{DateTime, Local};
+
+use super::downloader::Resolution;
+
+/// Written into each lecture's cache folder when its download starts
+const INFO_FILE: &str = "info.json";
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CacheInfo {
+    resolution: Resolution,
+    total_chunks: usize,
+    last_access: DateTime<Local>,
+}
+
+/// A lecture in the cache
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheEntry {
+    pub ttid: i32,
+    /// In bytes
+    pub size: u64,
+    pub chunks: usize,
+    /// Unknown for lectures cached before this was recorded
+    pub total_chunks: Option<usize>,
+    pub last_access: DateTime<Local>,
+}
+
+/// Records that a lecture's download has started, and how many chunks it has
+pub async fn touch(dir: &Path, resolution: Resolution, total_chunks: usize) -> Result<()> {
+    let info = CacheInfo {
+        resolution,
+        total_chunks,
+        last_access: Local::now(),
+    };
+
+    tokio::fs::write(
+        dir.join(INFO_FILE),
+        serde_json::to_vec(&info).context("Failed to serialize cache info!")?,
+    )
+    .await
+    .context("Failed to write cache info!")
+}
+
+fn dir_size(path: &Path) -> u64 {
+    std::fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+fn read_entry(path: &Path, ttid: i32) -> Result<CacheEntry> {
+    let info = std::fs::read(path.join(INFO_FILE))
+        .ok()
+        .and_then(|info| serde_json::from_slice::<CacheInfo>(&info).ok());
+
+    // Chunks of other resolutions may be left over, and are not part of this download
+    let suffix = info
+        .as_ref()
+        .map(|info| format!("_{}.ts", info.resolution))
+        .unwrap_or_else(|| ".ts".to_string());
+
+    let chunks = std::fs::read_dir(path.join("ts_store"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            // Chunks still being written aren't cached yet
+            name.ends_with(&suffix) && !name.contains(".partial.")
+        })
+        .count();
+
+    let last_access = match &info {
+        Some(info) => info.last_access,
+        None => std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .context("Failed to read cache entry's modified time!")?
+            .into(),
+    };
+
+    Ok(CacheEntry {
+        ttid,
+        size: dir_size(path),
+        chunks,
+        total_chunks: info.map(|info| info.total_chunks),
+        last_access,
+    })
+}
+
+/// Space the cache takes up
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheSize {
+    pub bytes: u64,
+    pub lectures: usize,
+}
+
+pub async fn size(cache: PathBuf) -> Result<CacheSize> {
+    tokio::task::spawn_blocking(move || {
+        let lectures = std::fs::read_dir(&cache)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with("Lecture_"))
+            .count();
+
+        CacheSize {
+            bytes: dir_size(&cache),
+            lectures,
+        }
+    })
+    .await
+    .context("Cache size task failed!")
+}
+
+/// Lists every lecture in the cache, least recently used first
+pub async fn list(cache: PathBuf) -> Result<Vec<CacheEntry>> {
+    tokio::task::spawn_blocking(move || {
+        if !cache.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut entries = vec![];
+
+        for entry in std::fs::read_dir(&cache).context("Failed to read cache directory!")? {
+            let entry = entry.context("Failed to read cache entry!")?;
+
+            let Some(ttid) = entry
+                .file_name()
+                .to_string_lossy()
+                .strip_prefix("Lecture_")
+                .and_then(|ttid| ttid.parse().ok())
+            else {
+                continue;
+            };
+
+            match read_entry(&entry.path(), ttid) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!("Skipping cache entry of lecture {ttid}: {e}"),
+            }
+        }
+
+        entries.sort_by_key(|entry| entry.last_access);
+
+        Ok(entries)
+    })
+    .await
+    .context("Cache listing task failed!")?
+}
+
+/// Removes a lecture from the cache
+pub async fn remove(cache: &Path, ttid: i32) -> Result<()> {
+    let path = cache.join(format!("Lecture_{ttid}"));
+
+    if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+        tokio::fs::remove_dir_all(&path)
+            .await
+            .with_context(|| format!("Failed to remove lecture {ttid} from the cache!"))?;
+    }
+
+    Ok(())
+}
+
+/// Removes the least recently used lectures until the cache fits in `max_size` bytes. Lectures
+/// in `keep` are being downloaded, and are left alone. Returns the ttids of removed lectures
+#[instrument(skip(cache, keep))]
+pub async fn evict(cache: &Path, max_size: u64, keep: &[i32]) -> Result<Vec<i32>> {
+    let entries = list(cache.to_path_buf()).await?;
+    let mut size = entries.iter().map(|entry| entry.size).sum::<u64>();
+    let mut evicted = vec![];
+
+    for entry in entries {
+        if size <= max_size {
+            break;
+        }
+
+        if keep.contains(&entry.ttid) {
+            continue;
+        }
+
+        info!(
+            "Evicting lecture {} ({} bytes, last used {}) from the cache",
+            entry.ttid, entry.size, entry.last_access
+        );
+        remove(cache, entry.ttid).await?;
+        size -= entry.size;
+        evicted.push(entry.ttid);
+    }
+
+    Ok(evicted)
+}
+
+/// Moves every lecture in the `from` cache into the `to` cache, merging with lectures that are
+/// already there. Returns how many lectures were moved
+#[instrument]
+pub async fn migrate(from: PathBuf, to: PathBuf) -> Result<usize> {
+    tokio::task::spawn_blocking(move || {
+        if !from.exists() {
+            return Ok(0);
+        }
+
+        std::fs::create_dir_all(&to).context("Failed to create new cache directory!")?;
+
+        let mut moved = 0;
+
+        for entry in std::fs::read_dir(&from).context("Failed to read cache directory!")? {
+            let entry = entry.context("Failed to read cache entry!")?;
+            let name = entry.file_name();
+
+            if !name.to_string_lossy().starts_with("Lecture_") {
+                continue;
+            }
+
+            info!("Moving {name:?} to the new cache");
+            move_dir(&entry.path(), &to.join(&name))
+                .with_context(|| format!("Failed to move {name:?} to the new cache!"))?;
+            moved += 1;
+        }
+
+        Ok(moved)
+    })
+    .await
+    .context("Cache migration task failed!")?
+}
+
+/// Renames the directory if possible, and otherwise copies it over, as when moving across disks
+fn move_dir(from: &Path, to: &Path) -> Result<()> {
+    if !to.exists() && std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    copy_dir(from, to)?;
+    std::fs::remove_dir_all(from).context("Failed to remove old cache entry!")
+}
+
+/// Copies the directory, leaving files that already exist at the destination as they are
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    std::fs::create_dir_all(to).context("Failed to create directory!")?;
+
+    for entry in std::fs::read_dir(from).context("Failed to read directory!")? {
+        let entry = entry.context("Failed to read directory entry!")?;
+        let target = to.join(entry.file_name());
+
+        if entry
+            .file_type()
+            .context("Failed to read file type!")?
+            .is_dir()
+        {
+            copy_dir(&entry.path(), &target)?;
+        } else if !target.exists() {
+            std::fs::copy(entry.path(), &target)
+                .with_context(|| format!("Failed to copy `{}`!", entry.path().display()))?;
+        }
+    }
+
+    Ok(())
+}
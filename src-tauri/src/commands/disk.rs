@@ -0,0 +1,214 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock,
+    },
+    time::Duration,
+};
+
+use crate::prelude::*;
+
+use tokio::{sync::watch, task::JoinHandle};
+
+/// How often free space is checked while downloading
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The disks each running watcher found nearly full, which downloads writing to the same disk
+/// are held off for
+static LOW_SPACE: LazyLock<watch::Sender<BTreeMap<u64, Vec<Option<DiskId>>>>> =
+    LazyLock::new(|| watch::Sender::new(BTreeMap::new()));
+
+static NEXT_WATCHER: AtomicU64 = AtomicU64::new(0);
+
+/// Free space in bytes on the disk the path is on, or would be on once created
+pub fn available_space(path: &Path) -> Result<u64> {
+    let existing = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .context("No part of the path exists!")?;
+
+    fs4::available_space(existing)
+        .with_context(|| format!("Failed to read free space of `{}`!", existing.display()))
+}
+
+/// Identifies a disk, as far as sharing free space goes
+#[cfg(unix)]
+pub type DiskId = u64;
+/// Identifies a disk, as far as sharing free space goes. Drive letters are the closest we get here
+#[cfg(not(unix))]
+pub type DiskId = PathBuf;
+
+/// The disk the path is on, or would be on once created, if it can be told. This touches the
+/// disk, so it shouldn't be called from async code
+pub fn disk_id(path: &Path) -> Option<DiskId> {
+    let existing = path.ancestors().find(|ancestor| ancestor.exists())?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        std::fs::metadata(existing)
+            .ok()
+            .map(|metadata| metadata.dev())
+    }
+
+    #[cfg(not(unix))]
+    {
+        existing
+            .components()
+            .next()
+            .map(|component| PathBuf::from(component.as_os_str()))
+    }
+}
+
+/// Disks that can't be told apart are assumed to be the same, so that low space isn't missed
+fn shares_disk(a: &Option<DiskId>, b: &Option<DiskId>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a == b,
+        _ => true,
+    }
+}
+
+/// Whether both paths are on the same disk, so that they share free space
+pub fn same_disk(a: &Path, b: &Path) -> bool {
+    shares_disk(&disk_id(a), &disk_id(b))
+}
+
+/// Space needed against space available for a batch of downloads, in bytes
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiskSpaceReport {
+    pub temp_required: u64,
+    pub temp_available: u64,
+    pub destination_required: u64,
+    pub destination_available: u64,
+    /// The cache and the download folder are on the same disk, so share the available space
+    pub same_disk: bool,
+    pub sufficient: bool,
+    /// Lectures whose size could not be estimated, and so are not counted
+    pub unknown: Vec<i32>,
+}
+
+impl DiskSpaceReport {
+    pub fn new(
+        temp: &Path,
+        destination: &Path,
+        temp_required: u64,
+        destination_required: u64,
+        unknown: Vec<i32>,
+    ) -> Result<Self> {
+        let temp_available = available_space(temp)?;
+        let destination_available = available_space(destination)?;
+        let same_disk = same_disk(temp, destination);
+
+        let sufficient = if same_disk {
+            temp_required + destination_required <= temp_available
+        } else {
+            temp_required <= temp_available && destination_required <= destination_available
+        };
+
+        Ok(Self {
+            temp_required,
+            temp_available,
+            destination_required,
+            destination_available,
+            same_disk,
+            sufficient,
+            unknown,
+        })
+    }
+}
+
+/// Waits until there is enough free space on the disk `path` is on to keep writing to it
+pub async fn wait_for_space(path: &Path) {
+    if LOW_SPACE.borrow().is_empty() {
+        return;
+    }
+
+    let path = path.to_path_buf();
+    let disk = tokio::task::spawn_blocking(move || disk_id(&path))
+        .await
+        .ok()
+        .flatten();
+
+    let mut low_space = LOW_SPACE.subscribe();
+    let _ = low_space
+        .wait_for(|low| !low.values().flatten().any(|low| shares_disk(low, &disk)))
+        .await;
+}
+
+/// Keeps checking the free space of some paths while downloading, pausing downloads to any of
+/// them whenever it drops below a minimum and resuming them once there is room again. Stops when
+/// dropped
+pub struct SpaceWatcher {
+    id: u64,
+    task: JoinHandle<()>,
+}
+
+impl SpaceWatcher {
+    pub fn spawn(
+        paths: Vec<PathBuf>,
+        min_free: u64,
+        on_change: impl Fn(bool, u64) + Send + 'static,
+    ) -> Self {
+        let id = NEXT_WATCHER.fetch_add(1, Ordering::Relaxed);
+
+        let task = tokio::spawn(async move {
+            let mut was_low = false;
+
+            loop {
+                let checked = paths.clone();
+                let available = tokio::task::spawn_blocking(move || {
+                    checked
+                        .into_iter()
+                        .filter_map(|path| Some((available_space(&path).ok()?, disk_id(&path))))
+                        .collect::<Vec<_>>()
+                })
+                .await
+                .unwrap_or_default();
+
+                if let Some(lowest) = available.iter().map(|(free, _)| *free).min() {
+                    let low = available
+                        .into_iter()
+                        .filter(|(free, _)| *free < min_free)
+                        .map(|(_, disk)| disk)
+                        .collect::<Vec<_>>();
+
+                    LOW_SPACE.send_if_modified(|watchers| {
+                        let changed = watchers.get(&id).map_or(!low.is_empty(), |old| *old != low);
+                        if low.is_empty() {
+                            watchers.remove(&id);
+                        } else {
+                            watchers.insert(id, low.clone());
+                        }
+                        changed
+                    });
+
+                    let is_low = !low.is_empty();
+                    if is_low != was_low {
+                        if is_low {
+                            warn!("Only {lowest} bytes free, pausing downloads");
+                        } else {
+                            info!("{lowest} bytes free, resuming downloads");
+                        }
+                        on_change(is_low, lowest);
+                        was_low = is_low;
+                    }
+                }
+
+                tokio::time::sleep(CHECK_INTERVAL).await;
+            }
+        });
+
+        Self { id, task }
+    }
+}
+
+impl Drop for SpaceWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+        LOW_SPACE.send_if_modified(|watchers| watchers.remove(&self.id).is_some());
+    }
+}
@@ -1,39 +1,199 @@
 use std::{
-    collections::HashMap, fmt::Display, future::Future, io::Write, sync::Arc, time::Duration,
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    future::Future,
+    path::Path,
+    sync::Arc,
+    time::Duration,
 };
 
 use crate::prelude::*;
 
 use tauri_plugin_http::reqwest::{self, Client};
-use tokio::{io::AsyncWriteExt, task::JoinSet};
+use tokio::{io::AsyncWriteExt, sync::Semaphore, task::JoinSet};
 use tracing::info;
 
-use std::sync::LazyLock;
+use std::sync::{LazyLock, RwLock};
 
-use crate::commands::get_temp;
+use crate::{commands::get_temp, redact::redact_userinfo};
 
-use super::Settings;
+use super::{auth::Token, cache, disk, partial, Settings};
 
-// A static instance of a client, so that just one client is used for all requests
-static CLIENT: LazyLock<Client> = LazyLock::new(Client::new);
-const BASE: &str = dotenvy_macro::dotenv!("BASE");
+mod remotes;
+pub mod throttle;
+
+// A static instance of a client, so that just one client is used for all requests. It's
+// rebuilt whenever the HTTP settings change
+static CLIENT: LazyLock<RwLock<Client>> = LazyLock::new(|| {
+    RwLock::new(
+        Client::builder()
+            .connect_timeout(DEFAULT_CONNECT_TIMEOUT)
+            .read_timeout(DEFAULT_READ_TIMEOUT)
+            .build()
+            .unwrap_or_default(),
+    )
+});
+pub const BASE: &str = dotenvy_macro::dotenv!("BASE");
 static REMOTES: LazyLock<Vec<&str>> =
     LazyLock::new(|| serde_json::from_str(dotenvy_macro::dotenv!("VITE_REMOTES")).unwrap());
 static MAX_RETRY_COUNT: LazyLock<usize> =
     LazyLock::new(|| dotenvy_macro::dotenv!("MAX_RETRY_COUNT").parse().unwrap());
 
+/// Timeouts used when the settings leave them empty. A request fails if it doesn't connect, or
+/// stops receiving data, for this long
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Wait after the first failed attempt of a retried task, doubled after each of the next ones
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Size of the buffer each chunk is written to disk through
+const CHUNK_BUFFER_SIZE: usize = 64 * 1024;
+/// Chunks fetched at once across every download. Chunks are never held whole, each one is streamed
+/// straight into its file, so in flight a chunk only holds its write buffer and the body frame
+/// being written. Chunk downloads are bounded by `MAX_CONCURRENT_CHUNKS` times that: 32 * 64KiB
+/// of buffers, plus up to one frame each, which hyper caps at its read buffer size (about 400KiB)
+const MAX_CONCURRENT_CHUNKS: usize = 32;
+static CHUNK_PERMITS: LazyLock<Semaphore> = LazyLock::new(|| Semaphore::new(MAX_CONCURRENT_CHUNKS));
+
+/// The shared client. Clients are reference counted, so this is cheap
+pub fn client() -> Client {
+    CLIENT.read().unwrap().clone()
+}
+
+/// How requests are made, for networks that need a proxy or inspect TLS traffic
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct HttpConfig {
+    /// For example `http://proxy:3128` or `socks5://127.0.0.1:1080`
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// PEM files of root certificates to trust on top of the built-in ones
+    #[serde(default)]
+    pub root_certificates: Vec<String>,
+    /// In seconds
+    #[serde(default)]
+    pub connect_timeout: Option<u64>,
+    /// In seconds, how long to wait for more data from a response
+    #[serde(default)]
+    pub read_timeout: Option<u64>,
+    #[serde(default)]
+    pub user_agent: Option<String>,
+}
+
+// Proxy addresses may carry a username and password, which must stay out of the logs
+impl std::fmt::Debug for HttpConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpConfig")
+            .field("proxy", &self.proxy.as_deref().map(redact_userinfo))
+            .field("root_certificates", &self.root_certificates)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("read_timeout", &self.read_timeout)
+            .field("user_agent", &self.user_agent)
+            .finish()
+    }
+}
+
+/// Builds a client from the config and uses it for every request from now on
+#[instrument(skip_all)]
+pub async fn configure_client(config: &HttpConfig) -> Result<()> {
+    install_client(build_client(config).await?, config);
+    Ok(())
+}
+
+/// Builds a client from the config without using it yet, which fails on a bad proxy or certificate
+pub async fn build_client(config: &HttpConfig) -> Result<Client> {
+    let mut builder = Client::builder();
+
+    if let Some(proxy) = &config.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy).context(format!(
+            "Invalid proxy address `{}`!",
+            redact_userinfo(proxy)
+        ))?);
+    }
+
+    for path in &config.root_certificates {
+        let pem = tokio::fs::read(path)
+            .await
+            .context(format!("Failed to read certificate `{path}`!"))?;
+        builder = builder.add_root_certificate(
+            reqwest::Certificate::from_pem(&pem)
+                .context(format!("Invalid certificate `{path}`!"))?,
+        );
+    }
+
+    builder = builder
+        .connect_timeout(
+            config
+                .connect_timeout
+                .map_or(DEFAULT_CONNECT_TIMEOUT, Duration::from_secs),
+        )
+        .read_timeout(
+            config
+                .read_timeout
+                .map_or(DEFAULT_READ_TIMEOUT, Duration::from_secs),
+        );
+
+    if let Some(user_agent) = &config.user_agent {
+        builder = builder.user_agent(user_agent);
+    }
+
+    builder.build().context("Failed to build HTTP client!")
+}
+
+/// Uses a client built from the config for every request from now on
+pub fn install_client(client: Client, config: &HttpConfig) {
+    *CLIENT.write().unwrap() = client;
+
+    info!("Configured HTTP client: {config:?}");
+}
+
 /// References static client to perform a GET request with the token auth header
-async fn get(url: &str, id_token: &str, failure_message: &str) -> Result<reqwest::Response> {
-    CLIENT
+async fn send(
+    url: &str,
+    id_token: &str,
+    offset: u64,
+    failure_message: &str,
+) -> Result<reqwest::Response> {
+    // Fails if no data is received for the configured read timeout
+    let mut request = client()
         .get(url)
-        // If the request does not recieve any data within 30s, it fails
-        .timeout(Duration::new(30, 0))
-        .header(reqwest::header::AUTHORIZATION, format!("Bearer {id_token}"))
+        .header(reqwest::header::AUTHORIZATION, format!("Bearer {id_token}"));
+
+    if offset > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={offset}-"));
+    }
+
+    request
         .send()
         .await
         .context(format!("Connection timed out when attempting to GET data from URL \"{url}\"!\n{failure_message}"))
 }
 
+/// Performs a GET request with the current token, refreshing it and trying once more if it has expired
+pub async fn get(url: &str, id_token: &Token, failure_message: &str) -> Result<reqwest::Response> {
+    get_from(url, id_token, 0, failure_message).await
+}
+
+/// Like [`get`], but asks for the response to start `offset` bytes in. Remotes may ignore this
+/// and send all of it, which shows up as a status other than 206
+pub async fn get_from(
+    url: &str,
+    id_token: &Token,
+    offset: u64,
+    failure_message: &str,
+) -> Result<reqwest::Response> {
+    let stale = id_token.get();
+    let res = send(url, &stale, offset, failure_message).await?;
+
+    if res.status() != reqwest::StatusCode::UNAUTHORIZED {
+        return Ok(res);
+    }
+
+    info!("Token was rejected when fetching \"{url}\", refreshing it");
+    let fresh = id_token.refresh(&stale).await?;
+    send(url, &fresh, offset, failure_message).await
+}
+
 #[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub enum Resolution {
     /// 480p
@@ -56,10 +216,10 @@ impl Display for Resolution {
     }
 }
 
-#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Views {
-    left: bool,
-    right: bool,
+    pub left: bool,
+    pub right: bool,
 }
 
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
@@ -70,8 +230,9 @@ pub struct TrackInfo {
 
 async fn check_available(url: &str) -> bool {
     // Check if the host returns anything - ie. it's available to download from
-    // If this does not recieve a response, it's considered unavailable
-    if let Ok(res) = CLIENT.head(url).timeout(Duration::new(5, 0)).send().await {
+    // If this does not recieve a response within the configured timeouts, it's considered
+    // unavailable
+    if let Ok(res) = client().head(url).send().await {
         if res.status().is_success() {
             return true;
         }
@@ -95,7 +256,12 @@ async fn retry<T, O: Future<Output = Result<T>>, F: Fn() -> O>(
                     *MAX_RETRY_COUNT,
                     if i == *MAX_RETRY_COUNT { "Not r" } else { "R" }
                 );
-                error = Some(err)
+                error = Some(err);
+
+                // Give a struggling remote a moment before asking it again
+                if i < *MAX_RETRY_COUNT {
+                    tokio::time::sleep(RETRY_BACKOFF * (1 << (i - 1).min(4))).await;
+                }
             }
             Ok(v) => return Ok(v),
         };
@@ -106,45 +272,298 @@ async fn retry<T, O: Future<Output = Result<T>>, F: Fn() -> O>(
     ))
 }
 
-#[instrument(fields(ttid))]
-async fn select_base<'a>(ttid: usize) -> Result<&'a str> {
-    info!("Parsing remote url json for {ttid}");
+/// Fetches which resolutions and views a lecture is available in
+async fn fetch_tracks(id_token: &Token, ttid: usize) -> Result<TrackInfo> {
+    let m3u8_info = format!("{BASE}/impartus/ttid/{ttid}/m3u8/info");
+
+    info!("Fetching index playlist file for {ttid}");
+
+    // I hope you love these beautiful waterfalls @TheComputerM :)
+    // Get impartus .m3u8 file
+    let m3u8_index_bytes = retry(
+        async || {
+            get(&m3u8_info, id_token, "Failed to fetch index playlist file!")
+                .await?
+                .bytes()
+                .await
+                .context("Failed to read contents of playlist info file!")
+        },
+        "Get m3u8 index bytes",
+    )
+    .await?;
+
+    info!("Fetched playlist json, now parsing it for {ttid}");
+
+    let m3u8_tracks = serde_json::from_slice::<TrackInfo>(&m3u8_index_bytes)
+        .context("Failed to parse track json!")?;
+
+    info!("Finished parsing playlist json file for {ttid}");
 
-    info!("Finding fastest remote url for {ttid}");
-    // Pick the fastest server to download from
-    let mut set_base = "";
-    // If the client failed to connect to any of the available hosts
-    let mut failed = true;
+    Ok(m3u8_tracks)
+}
 
-    let mut set = JoinSet::new();
-    for base in REMOTES.iter() {
-        set.spawn(async move { (check_available(base).await, base) });
+/// The address of the playlist of the selected resolution
+fn select_address(m3u8_tracks: &TrackInfo, resolution: Resolution) -> Result<String> {
+    Ok(if let Resolution::HighRes = resolution {
+        m3u8_tracks
+            .tracks
+            .get("1280x720")
+            .context("Failed to get 1280x720p video playlist")?
+            .last()
+            .context("Failed to get first link in 1280x720p video playlist")?
+    } else {
+        m3u8_tracks
+            .tracks
+            .get("854x480")
+            .context("Failed to get 854x480 video playlist")?
+            .last()
+            .context("Failed to get first link in 854x480 video playlist")?
     }
+    .clone())
+}
 
-    // Run all the ping-functions at the same time, and wait for the first successful response
-    while let Some(res) = set.join_next().await {
-        match res {
-            Ok((is_available, base)) => {
-                failed = !is_available;
-                set_base = base;
-                if is_available {
-                    break;
+/// The remote picked in settings if it's reachable, otherwise the fastest one
+async fn select_download_base<'a>(
+    base: &'a Option<String>,
+    address: &str,
+    id_token: &Arc<Token>,
+) -> Result<&'a str> {
+    // If a base has been dictated by settings
+    if let Some(base) = base.as_ref() {
+        info!("Using download source {base} from user settings");
+        if check_available(base).await {
+            Ok(base.as_str())
+        } else {
+            error!("Failed to connect to base {base}");
+            Err(anyhow::Error::msg(format!("Failed to connect to download source `{base}`! Check your connection and try again, or try to a different download source.")))
+        }
+    } else {
+        retry(
+            async || remotes::select_base(address, id_token).await,
+            "select_base",
+        )
+        .await
+    }
+}
+
+/// Fetches the .m3u8 file that lists the video chunks
+async fn fetch_playlist(download_base: &str, address: &str, id_token: &Token) -> Result<String> {
+    let selected_m3u8 = remotes::playlist_url(download_base, address);
+
+    info!("Selected playlist file url: {selected_m3u8}");
+
+    info!("Fetching main playlist file");
+
+    retry(
+        async || {
+            get(&selected_m3u8, id_token, "Failed to fetch playlist file!")
+                .await?
+                .text()
+                .await
+                .context("Failed to read contents of playlist file!")
+        },
+        "Get m3u8 playlist file",
+    )
+    .await
+}
+
+/// Keeps the lecture's track info and remote playlist in its cache, so it can be finished
+/// offline later
+async fn save_source(
+    temp_location: &Path,
+    resolution: Resolution,
+    m3u8_tracks: &TrackInfo,
+    playlist: &str,
+) -> Result<()> {
+    tokio::fs::write(
+        temp_location.join("tracks.json"),
+        serde_json::to_vec(m3u8_tracks).context("Failed to serialize track info!")?,
+    )
+    .await
+    .context("Failed to cache track info!")?;
+
+    tokio::fs::write(
+        temp_location.join(format!("source_{resolution}.m3u8")),
+        playlist,
+    )
+    .await
+    .context("Failed to cache playlist!")
+}
+
+/// The track info and remote playlist saved by [`save_source`]
+async fn load_source(temp_location: &Path, resolution: Resolution) -> Result<(TrackInfo, String)> {
+    let not_cached = "This lecture has not been cached, so it can't be downloaded offline!";
+
+    let m3u8_tracks = serde_json::from_slice(
+        &tokio::fs::read(temp_location.join("tracks.json"))
+            .await
+            .context(not_cached)?,
+    )
+    .context("Failed to parse cached track info!")?;
+
+    let playlist =
+        tokio::fs::read_to_string(temp_location.join(format!("source_{resolution}.m3u8")))
+            .await
+            .context(not_cached)?;
+
+    Ok((m3u8_tracks, playlist))
+}
+
+/// Length in seconds of the chunk an `#EXTINF:<duration>,[title]` header comes before
+fn chunk_duration(header: &str) -> Option<f64> {
+    header
+        .strip_prefix("#EXTINF:")
+        .and_then(|info| info.split(',').next())
+        .and_then(|duration| duration.parse::<f64>().ok())
+}
+
+/// Rebuilds a lecture's playlist from the local playlists, key and chunks an earlier download
+/// left in the cache, as long as all of them are still there
+async fn load_local(temp: &str, filename: &str) -> Result<Playlist> {
+    let not_cached = "This lecture has not been cached, so it can't be downloaded offline!";
+
+    let side1 = format!("{temp}/{filename}_side_1.m3u8");
+    let side2 = format!("{temp}/{filename}_side_2.m3u8");
+    let key = format!("{temp}/{filename}.key.key");
+
+    // ffmpeg always reads the first side, even when only one view is available
+    let left = tokio::fs::read_to_string(&side1)
+        .await
+        .context(not_cached)?;
+    let right = tokio::fs::read_to_string(&side2).await.ok();
+
+    if !tokio::fs::try_exists(&key).await.unwrap_or(false) {
+        return Err(anyhow::Error::msg(
+            "The key of this lecture has not been cached, so it can't be downloaded offline!",
+        ));
+    }
+
+    let mut durations = [0f64; 2];
+    let mut chunks = 0;
+    let mut missing = 0;
+
+    for (side, playlist) in [Some(&left), right.as_ref()].into_iter().enumerate() {
+        for line in playlist.into_iter().flat_map(|playlist| playlist.lines()) {
+            if let Some(duration) = chunk_duration(line) {
+                durations[side] += duration;
+            } else if !line.starts_with('#') && !line.is_empty() {
+                chunks += 1;
+                if !tokio::fs::try_exists(line).await.unwrap_or(false) {
+                    missing += 1;
                 }
             }
+        }
+    }
+
+    if missing > 0 {
+        return Err(anyhow::Error::msg(format!(
+            "{missing} of the {chunks} chunks of this lecture have not been cached, so it can't be downloaded offline!"
+        )));
+    }
+
+    Ok(Playlist {
+        side1,
+        side2: right.is_some().then_some(side2),
+        key,
+        duration: durations[0].max(durations[1]),
+        views: Views {
+            left: true,
+            right: right.is_some(),
+        },
+    })
+}
+
+/// Rough bitrates of a single view, for when nothing of a lecture has been downloaded yet
+const HIGH_RES_BYTES_PER_SEC: f64 = 80_000.0;
+const LOW_RES_BYTES_PER_SEC: f64 = 45_000.0;
+
+/// How much space a lecture's chunks take up, in bytes
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SizeEstimate {
+    pub total: u64,
+    /// Already in the cache from an earlier attempt
+    pub cached: u64,
+}
+
+/// Estimates the size of a lecture from its playlist. When some of its chunks have been cached
+/// already their average size is used, otherwise a typical bitrate for the resolution
+pub async fn estimate_size(
+    settings: &Settings,
+    id_token: &Arc<Token>,
+    ttid: usize,
+) -> Result<SizeEstimate> {
+    let Settings {
+        resolution,
+        base,
+        offline,
+        ..
+    } = settings;
 
-            Err(_) => failed = true,
+    let temp_location = get_temp(settings).join(format!("Lecture_{ttid}"));
+
+    let playlist = if *offline {
+        load_source(&temp_location, *resolution).await?.1
+    } else {
+        let m3u8_tracks = fetch_tracks(id_token, ttid).await?;
+        let address = select_address(&m3u8_tracks, *resolution)?;
+        let download_base = select_download_base(base, &address, id_token).await?;
+        fetch_playlist(download_base, &address, id_token).await?
+    };
+
+    let (mut segments, mut duration) = (0u64, 0f64);
+    for line in playlist.lines() {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            segments += 1;
+            duration += info
+                .split(',')
+                .next()
+                .and_then(|duration| duration.parse::<f64>().ok())
+                .unwrap_or_default();
         }
     }
 
-    // Is 5s a good enough amount of time to decide if a server is unavailable?
-    // Or should it try connecting to the default server again - waiting as long as it takes?
-    if failed {
-        return Err(anyhow::Error::msg(
-            "Failed to connect to any available download sources! Check your connection and try again.",
-        ));
+    let (mut cached_count, mut cached) = (0u64, 0u64);
+    let suffix = format!("_{resolution}.ts");
+    let ts_store = get_temp(settings)
+        .join(format!("Lecture_{ttid}"))
+        .join("ts_store");
+
+    if let Ok(mut entries) = tokio::fs::read_dir(ts_store).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if entry.file_name().to_string_lossy().ends_with(&suffix) {
+                cached_count += 1;
+                cached += entry.metadata().await.map(|m| m.len()).unwrap_or_default();
+            }
+        }
     }
 
-    Ok(set_base)
+    let total = match cached.checked_div(cached_count) {
+        Some(average) => average * segments,
+        None => {
+            let bytes_per_sec = if let Resolution::HighRes = resolution {
+                HIGH_RES_BYTES_PER_SEC
+            } else {
+                LOW_RES_BYTES_PER_SEC
+            };
+            (duration * bytes_per_sec) as u64
+        }
+    };
+
+    Ok(SizeEstimate {
+        total: total.max(cached),
+        cached,
+    })
+}
+
+/// Local playlists of a downloaded lecture, ready to be muxed
+pub struct Playlist {
+    pub side1: String,
+    pub side2: Option<String>,
+    /// Key the chunks are encrypted with
+    pub key: String,
+    /// Length of the lecture in seconds, as listed in the playlist
+    pub duration: f64,
+    pub views: Views,
 }
 
 // TODOS: Not in order of importance:
@@ -154,44 +573,32 @@ async fn select_base<'a>(ttid: usize) -> Result<&'a str> {
 pub async fn download_playlist(
     settings: Arc<Settings>,
     tx: tokio::sync::watch::Sender<f32>,
-    id_token: &str,
+    id_token: &Arc<Token>,
     ttid: usize,
     filename: &str,
-) -> Result<(String, Option<String>)> {
+) -> Result<Playlist> {
     let Settings {
-        resolution, base, ..
+        resolution,
+        base,
+        offline,
+        ..
     } = &*settings;
 
-    // If a base has been dictated by settings
-    let download_base = if let Some(base) = base.as_ref() {
-        info!("Using download source {base} from user settings");
-        if check_available(base).await {
-            base.as_str()
-        } else {
-            error!("Failed to connect to base {base}");
-            return Err(anyhow::Error::msg(format!("Failed to connect to download source `{base}`! Check your connection and try again, or try to a different download source.")));
-        }
-    } else {
-        retry(async || select_base(ttid).await, "select_base").await?
-    };
-
-    info!("Selected remote: {download_base} for {ttid}");
-
     // {temp}/multipartus-downloader/videos/Lecture_<lecture-ttid>
-    let temp_location = get_temp().join(format!("Lecture_{ttid}"));
+    let temp_location = get_temp(&settings).join(format!("Lecture_{ttid}"));
 
     let temp = temp_location.as_path().to_str().unwrap_or("./tmp");
 
     info!("Creating temp directory at {temp}");
 
     // Create this temp location if it doesn't exist
-    std::fs::create_dir_all(temp)
+    tokio::fs::create_dir_all(temp)
+        .await
         .context(format!("Failed to create temporary directory {}!", temp))?;
 
     info!("Created temp directory at {temp}");
 
-    // URLs to get data from
-    let m3u8_info = format!("{BASE}/impartus/ttid/{ttid}/m3u8/info");
+    // URL to get data from
     let key_url = format!("{BASE}/impartus/ttid/{ttid}/key");
 
     // Temp locations to store the files used for generating outputs
@@ -199,96 +606,67 @@ pub async fn download_playlist(
     let m3u8_side2_file_path = format!("{temp}/{filename}_side_2.m3u8");
     let key_file_path = format!("{temp}/{filename}.key.key");
 
-    info!("Fetching index playlist file for {ttid}");
-
-    // I hope you love these beautiful waterfalls @TheComputerM :)
-    // Get impartus .m3u8 file
-    let m3u8_index_bytes = retry(
-        async || {
-            get(&m3u8_info, id_token, "Failed to fetch index playlist file!")
-                .await?
-                .bytes()
-                .await
-                .context("Failed to read contents of playlist info file!")
-        },
-        "Get m3u8 index bytes",
-    )
-    .await?;
-
-    info!("Fetched playlist json, now parsing it for {ttid}");
-
-    let m3u8_tracks = serde_json::from_slice::<TrackInfo>(&m3u8_index_bytes)
-        .context("Failed to parse track json!")?;
+    let (m3u8_tracks, m3u8_in_text, download_base) = if *offline {
+        info!("Offline, using the cached playlist of {ttid}");
 
-    info!("Finished parsing playlist json file for {ttid}");
+        match load_source(&temp_location, *resolution).await {
+            Ok((m3u8_tracks, m3u8_in_text)) => (m3u8_tracks, m3u8_in_text, None),
+            // Lectures cached before the source was kept still have their local playlists
+            Err(e) => {
+                info!("No cached source of {ttid}, falling back to its local playlists: {e}");
 
-    // Select the correct resolution
-    let selected_m3u8 = {
-        let address = if let Resolution::HighRes = resolution {
-            m3u8_tracks
-                .tracks
-                .get("1280x720")
-                .context("Failed to get 1280x720p video playlist")?
-                .last()
-                .context("Failed to get first link in 1280x720p video playlist")?
-        } else {
-            m3u8_tracks
-                .tracks
-                .get("854x480")
-                .context("Failed to get 854x480 video playlist")?
-                .last()
-                .context("Failed to get first link in 854x480 video playlist")?
+                let playlist = load_local(temp, filename).await?;
+                tx.send(100.0).unwrap_or(());
+                return Ok(playlist);
+            }
         }
-        .clone();
-        download_base.to_string() + "/api/fetchvideo?tag=LC&inm3u8=" + &address
-    };
+    } else {
+        let m3u8_tracks = fetch_tracks(id_token, ttid).await?;
 
-    info!("Selected playlist file url: {selected_m3u8} for {ttid}");
+        let address = select_address(&m3u8_tracks, *resolution)?;
 
-    info!("Fetching main playlist file for {ttid}");
+        let download_base = select_download_base(base, &address, id_token).await?;
 
-    // Get .m3u8 file that contains the video chunks
-    let m3u8_in_text = retry(
-        async || {
-            get(&selected_m3u8, id_token, "Failed to fetch playlist file!")
-                .await?
-                .text()
-                .await
-                .context("Failed to read contents of playlist file!")
-        },
-        "Get m3u8 playlist file",
-    )
-    .await?;
+        info!("Selected remote: {download_base} for {ttid}");
 
-    info!("Fetched main playlist file. Fetching key file for {ttid}");
+        let m3u8_in_text = fetch_playlist(download_base, &address, id_token).await?;
 
-    // get impartus key
-    let key = retry(
-        async || {
-            get(&key_url, id_token, "Failed to fetch key!")
-                .await?
-                .bytes()
-                .await
-                .context("Failed to read key!")
-        },
-        "Get key file for decrypting incoming chunks",
-    )
-    .await?
-    .to_vec();
-
-    info!("Fetched key file. Opening key file for {ttid}");
+        let _ = save_source(&temp_location, *resolution, &m3u8_tracks, &m3u8_in_text)
+            .await
+            .inspect_err(|e| warn!("Failed to cache playlist of {ttid}: {e}"));
 
-    // write it to .key file for ffmpeg to deal with it later
-    let mut key_out = std::fs::File::create(&key_file_path)
-        .context(format!("Failed to create `.key` file at {key_file_path}!"))?;
+        (m3u8_tracks, m3u8_in_text, Some(download_base))
+    };
 
-    key_out
-        .write(&key)
-        .context("Failed to write key contents to .key file!")?;
+    if *offline {
+        if !tokio::fs::try_exists(&key_file_path).await.unwrap_or(false) {
+            return Err(anyhow::Error::msg(
+                "The key of this lecture has not been cached, so it can't be downloaded offline!",
+            ));
+        }
+    } else {
+        info!("Fetched main playlist file. Fetching key file for {ttid}");
+
+        // get impartus key
+        let key = retry(
+            async || {
+                get(&key_url, id_token, "Failed to fetch key!")
+                    .await?
+                    .bytes()
+                    .await
+                    .context("Failed to read key!")
+            },
+            "Get key file for decrypting incoming chunks",
+        )
+        .await?;
 
-    key_out.flush().context("Failed to flush .key file!")?;
+        info!("Fetched key file. Opening key file for {ttid}");
 
-    drop(key_out);
+        // write it to .key file for ffmpeg to deal with it later
+        tokio::fs::write(&key_file_path, &key)
+            .await
+            .context(format!("Failed to write `.key` file at {key_file_path}!"))?;
+    }
 
     info!("Created key file for {ttid}");
 
@@ -316,11 +694,18 @@ pub async fn download_playlist(
     let ts_store_location = std::path::Path::new(&temp).join("ts_store");
 
     // Create the folder if it does not exist
-    std::fs::create_dir_all(&ts_store_location)
+    tokio::fs::create_dir_all(&ts_store_location)
+        .await
         .context("Failed to create `ts_store` directory!")?;
 
     let mut side2_file_path = None;
 
+    // Total length of each side
+    let mut durations = [0f64; 2];
+
+    // Chunks that are not in the cache yet
+    let mut pending = vec![];
+
     // Process each .ts file and create a local copy of it and add it to the out string
     loop {
         // Assuming the .m3u8 file matches the spec, it will always follow #header\nuri\n
@@ -387,6 +772,10 @@ pub async fn download_playlist(
 
         let out = if side == 1 { &mut out_1 } else { &mut out_2 };
 
+        if let Some(duration) = chunk_duration(header) {
+            durations[side as usize - 1] += duration;
+        }
+
         // Attach original header and path to file that will be created next
         *out += header;
         out.push('\n');
@@ -405,9 +794,43 @@ pub async fn download_playlist(
             continue;
         }
 
-        download_ts_file(ts_store_path, id_token, ts_url).await?;
+        pending.push(Chunk {
+            path: ts_store_path.to_string(),
+            url: ts_url.to_string(),
+        });
+    }
 
-        tx.send(perc_downloaded).unwrap_or(());
+    // Lets the cache tell how far along this lecture is, and when it was last worked on
+    let _ = cache::touch(&temp_location, *resolution, i as usize)
+        .await
+        .inspect_err(|e| warn!("Failed to record cache info of {ttid}: {e}"));
+
+    if let Some(download_base) = download_base {
+        // Spread the chunks across every healthy remote, unless the user has picked one
+        let bases = if base.is_some() {
+            vec![download_base.to_string()]
+        } else {
+            remotes::stripe(download_base).await
+        };
+
+        info!(
+            "Downloading {} chunks from {} for {ttid}",
+            pending.len(),
+            bases.join(", ")
+        );
+
+        let mut downloaded = i as usize - pending.len();
+        download_chunks(pending, bases, id_token, || {
+            downloaded += 1;
+            let perc_downloaded = ((downloaded as f32) / (number_of_ts_files as f32)) * 100.0f32;
+            tx.send(perc_downloaded).unwrap_or(());
+        })
+        .await?;
+    } else if !pending.is_empty() {
+        return Err(anyhow::Error::msg(format!(
+            "{} of the {i} chunks of this lecture have not been cached, so it can't be downloaded offline!",
+            pending.len()
+        )));
     }
 
     // End playlist
@@ -425,7 +848,14 @@ pub async fn download_playlist(
         write_m3u8(&m3u8_side2_file_path, out_2).await?;
     }
 
-    Ok((m3u8_side1_file_path, side2_file_path))
+    Ok(Playlist {
+        side1: m3u8_side1_file_path,
+        side2: side2_file_path,
+        key: key_file_path,
+        // Both sides are recorded at the same time, but only one of them may be available
+        duration: durations[0].max(durations[1]),
+        views: m3u8_tracks.views,
+    })
 }
 
 async fn write_m3u8(filepath: &String, out: String) -> Result<()> {
@@ -448,35 +878,269 @@ async fn write_m3u8(filepath: &String, out: String) -> Result<()> {
     Ok(())
 }
 
-async fn download_ts_file(file_path: &str, id_token: &str, url: &str) -> Result<()> {
-    let ts_data = retry(
+/// A chunk of the lecture that has not been downloaded yet
+struct Chunk {
+    path: String,
+    url: String,
+}
+
+/// Downloads chunks from all `bases` at once. Each remote picks up the next chunk as soon as it
+/// has finished its previous one, so faster remotes end up serving proportionally more chunks.
+/// If a remote fails, its chunk is handed to the others, and once all of them have failed the
+/// remaining chunks are downloaded from the next available remote
+async fn download_chunks(
+    chunks: Vec<Chunk>,
+    mut bases: Vec<String>,
+    id_token: &Arc<Token>,
+    mut on_chunk: impl FnMut(),
+) -> Result<()> {
+    let queue = Arc::new(std::sync::Mutex::new(VecDeque::from(chunks)));
+    let mut failover = remotes::Failover::new(&bases).await;
+    let (done_tx, mut done_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut last_error = None;
+
+    loop {
+        let mut set = JoinSet::new();
+
+        for base in bases.drain(..) {
+            let (queue, id_token, done_tx) = (queue.clone(), id_token.clone(), done_tx.clone());
+
+            set.spawn(async move {
+                loop {
+                    let Some(chunk) = queue.lock().unwrap().pop_front() else {
+                        return Ok(());
+                    };
+
+                    // Hold off while the disk is nearly full, rather than failing halfway through
+                    disk::wait_for_space(Path::new(&chunk.path)).await;
+
+                    let url = remotes::rebase(&chunk.url, &base);
+
+                    if let Err(err) = download_ts_file(&chunk.path, &id_token, &url).await {
+                        // Hand the chunk over to one of the other remotes
+                        queue.lock().unwrap().push_front(chunk);
+                        return Err((base, err));
+                    }
+
+                    let _ = done_tx.send(());
+                }
+            });
+        }
+
+        loop {
+            tokio::select! {
+                Some(()) = done_rx.recv() => on_chunk(),
+                res = set.join_next() => match res {
+                    None => break,
+                    Some(Ok(Ok(()))) => (),
+                    Some(Ok(Err((base, err)))) => {
+                        warn!("Remote {base} failed to serve a chunk: {err}");
+                        remotes::invalidate(&base).await;
+                        last_error = Some(err);
+                    }
+                    Some(Err(err)) => return Err(err).context("Chunk download task failed!"),
+                },
+            }
+        }
+
+        // Report chunks that finished right before their task did
+        while done_rx.try_recv().is_ok() {
+            on_chunk();
+        }
+
+        if queue.lock().unwrap().is_empty() {
+            return Ok(());
+        }
+
+        let Some(next) = failover.next() else {
+            error!("No remotes left to fall back to");
+            return Err(last_error.unwrap_or_else(|| {
+                anyhow::Error::msg("Failed to connect to any available download sources!")
+            }));
+        };
+
+        info!("Falling back to remote {next} for the remaining chunks");
+        bases.push(next);
+    }
+}
+
+/// Whether a response is the rest of a chunk, starting at `offset`
+fn resumes_at(
+    status: reqwest::StatusCode,
+    headers: &reqwest::header::HeaderMap,
+    offset: u64,
+) -> bool {
+    status == reqwest::StatusCode::PARTIAL_CONTENT
+        && headers
+            .get(reqwest::header::CONTENT_RANGE)
+            .and_then(|range| range.to_str().ok())
+            .is_some_and(|range| range.starts_with(&format!("bytes {offset}-")))
+}
+
+/// Streams a chunk to disk, so that only a small buffer of it is ever held in memory. Chunks
+/// cut off part way are resumed with a range request where the remote supports it
+async fn download_ts_file(file_path: &str, id_token: &Token, url: &str) -> Result<()> {
+    let _permit = CHUNK_PERMITS
+        .acquire()
+        .await
+        .context("Failed to wait for a chunk download slot!")?;
+
+    let partial_path = partial::path_for(Path::new(file_path))
+        .to_string_lossy()
+        .to_string();
+
+    retry(
         async || {
-            get(url, id_token, "Failed to fetch video chunk!")
-                .await?
-                .bytes()
+            throttle::request(remotes::remote_of(url)).await;
+
+            // Pick up where an earlier attempt left off
+            let offset = tokio::fs::metadata(&partial_path)
                 .await
-                .context("Failed to read video chunk!")
+                .map(|metadata| metadata.len())
+                .unwrap_or(0);
+
+            let mut res = get_from(url, id_token, offset, "Failed to fetch video chunk!").await?;
+
+            let resumed = offset > 0 && resumes_at(res.status(), res.headers(), offset);
+
+            if offset > 0 && !resumed {
+                info!("Could not resume `{partial_path}` at {offset} bytes, fetching all of it");
+
+                // Anything but the whole chunk is of no use without the partial one
+                if res.status() != reqwest::StatusCode::OK {
+                    res = get(url, id_token, "Failed to fetch video chunk!").await?;
+                }
+            }
+
+            if !res.status().is_success() {
+                return Err(anyhow::Error::msg(format!(
+                    "Failed to fetch video chunk! The server responded with {}",
+                    res.status()
+                )));
+            }
+
+            // The size the finished chunk should be, where the remote says
+            let expected = res
+                .content_length()
+                .map(|length| if resumed { offset + length } else { length });
+
+            // Create a local copy of the .ts file, or add on to the partial one
+            let ts_store = if resumed {
+                tokio::fs::OpenOptions::new()
+                    .append(true)
+                    .open(&partial_path)
+                    .await
+                    .context(format!("Failed to open `.ts` file at {partial_path}!"))?
+            } else {
+                tokio::fs::File::create(&partial_path)
+                    .await
+                    .context(format!("Failed to create `.ts` file at {partial_path}!"))?
+            };
+            let mut ts_store = tokio::io::BufWriter::with_capacity(CHUNK_BUFFER_SIZE, ts_store);
+
+            // Populate the .ts file as the data comes in
+            let streamed = async {
+                while let Some(bytes) = res.chunk().await.context("Failed to read video chunk!")? {
+                    throttle::consume(bytes.len()).await;
+
+                    ts_store
+                        .write_all(&bytes)
+                        .await
+                        .context("Failed to write video chunk!")?;
+                }
+                Ok::<_, anyhow::Error>(())
+            }
+            .await;
+
+            // Whatever arrived is on disk even if the stream was cut off, so the next attempt
+            // resumes from the right length
+            ts_store
+                .flush()
+                .await
+                .context("Failed to flush video chunk!")?;
+            ts_store
+                .get_ref()
+                .sync_all()
+                .await
+                .context("Failed to sync video chunk!")?;
+            streamed?;
+
+            let written = tokio::fs::metadata(&partial_path)
+                .await
+                .context(format!("Failed to read `.ts` file at {partial_path}!"))?
+                .len();
+
+            match expected {
+                Some(expected) if written < expected => Err(anyhow::Error::msg(format!(
+                    "Video chunk ended at {written} of {expected} bytes!"
+                ))),
+                Some(expected) if written > expected => {
+                    // Can't be resumed from, so start over next time
+                    let _ = tokio::fs::remove_file(&partial_path).await;
+                    Err(anyhow::Error::msg(format!(
+                        "Video chunk is {written} bytes, more than the {expected} expected!"
+                    )))
+                }
+                _ => Ok(()),
+            }
         },
         "Get chunk data",
     )
-    .await?
-    .to_vec();
+    .await?;
 
-    // Create a local copy of the .ts file
-    let mut ts_store = tokio::fs::File::create(&file_path)
-        .await
-        .context(format!("Failed to create `.ts` file at {file_path}!"))?;
+    partial::finish(Path::new(file_path)).await
+}
 
-    // Populate the .ts file
-    ts_store
-        .write(&ts_data)
-        .await
-        .context("Failed to write video chunk!")?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    ts_store
-        .flush()
-        .await
-        .context("Failed to flush video chunk!")?;
+    use reqwest::{
+        header::{HeaderMap, HeaderValue, CONTENT_RANGE},
+        StatusCode,
+    };
 
-    Ok(())
+    fn range(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_RANGE, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn resumes_a_partial_response_at_the_offset() {
+        assert!(resumes_at(
+            StatusCode::PARTIAL_CONTENT,
+            &range("bytes 1024-2047/2048"),
+            1024
+        ));
+    }
+
+    #[test]
+    fn does_not_resume_at_another_offset() {
+        assert!(!resumes_at(
+            StatusCode::PARTIAL_CONTENT,
+            &range("bytes 0-2047/2048"),
+            1024
+        ));
+        // A prefix of the offset isn't the offset
+        assert!(!resumes_at(
+            StatusCode::PARTIAL_CONTENT,
+            &range("bytes 10240-20479/20480"),
+            1024
+        ));
+    }
+
+    #[test]
+    fn does_not_resume_whole_responses() {
+        assert!(!resumes_at(
+            StatusCode::OK,
+            &range("bytes 1024-2047/2048"),
+            1024
+        ));
+        assert!(!resumes_at(
+            StatusCode::PARTIAL_CONTENT,
+            &HeaderMap::new(),
+            1024
+        ));
+    }
 }
@@ -0,0 +1,360 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
+
+use crate::prelude::*;
+
+use tokio::{sync::Mutex, task::JoinSet};
+
+use super::{get, Token, REMOTES};
+
+/// How long a throughput measurement is trusted before all remotes are probed again
+const REEVALUATE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Minimum fraction of the fastest remote's throughput that a remote needs to be striped across.
+/// Much slower remotes would only hold up the last few chunks of a lecture
+const STRIPE_THRESHOLD: f64 = 0.1;
+
+/// How long a remote gets to serve its probe segment before it's considered unavailable
+const PROBE_TIMEOUT: Duration = Duration::new(15, 0);
+
+/// How long a remote that failed a download is passed over for, before it's trusted again
+const COOLDOWN: Duration = Duration::from_secs(2 * 60);
+
+/// Throughput measured for a single remote
+#[derive(Debug, Clone, Copy)]
+pub struct RemoteThroughput {
+    pub base: &'static str,
+    pub bytes_per_sec: f64,
+}
+
+#[derive(Debug, Clone)]
+struct Measurement {
+    /// Remotes that served their probe segment, fastest first
+    remotes: Vec<RemoteThroughput>,
+    measured_at: Instant,
+    /// When each remote last failed a download
+    failures: HashMap<String, Instant>,
+}
+
+impl Measurement {
+    fn cooling_down(&self, base: &str) -> bool {
+        self.failures
+            .get(base)
+            .is_some_and(|failed_at| failed_at.elapsed() < COOLDOWN)
+    }
+
+    /// Measured remotes that haven't failed recently, fastest first
+    fn available(&self) -> Vec<RemoteThroughput> {
+        self.remotes
+            .iter()
+            .filter(|remote| !self.cooling_down(remote.base))
+            .copied()
+            .collect()
+    }
+}
+
+// The measurement is shared by every lecture downloaded in this session. Holding the lock
+// while probing makes concurrent downloads wait for a single probe instead of running their own
+static MEASUREMENT: LazyLock<Mutex<Option<Measurement>>> = LazyLock::new(|| Mutex::new(None));
+
+/// The url of a lecture's chunk playlist on the given remote
+pub fn playlist_url(base: &str, address: &str) -> String {
+    format!("{base}/api/fetchvideo?tag=LC&inm3u8={address}")
+}
+
+/// Points a `fetchvideo` url at another remote, keeping the rest of the url intact
+pub fn rebase(url: &str, base: &str) -> String {
+    match url.find("/api/fetchvideo") {
+        Some(index) => format!("{base}{}", &url[index..]),
+        None => url.to_string(),
+    }
+}
+
+/// The remote a `fetchvideo` url points at
+pub fn remote_of(url: &str) -> &str {
+    match url.find("/api/fetchvideo") {
+        Some(index) => &url[..index],
+        None => url,
+    }
+}
+
+/// Returns the url of the first chunk in a playlist
+fn first_segment(playlist: &str) -> Option<&str> {
+    playlist
+        .lines()
+        .skip_while(|line| !line.starts_with("#EXTINF"))
+        .nth(1)
+}
+
+/// Downloads the first chunk of the playlist from `base`, and measures how fast it arrived
+async fn probe(base: &'static str, address: &str, id_token: &Token) -> Result<f64> {
+    let playlist = get(
+        &playlist_url(base, address),
+        id_token,
+        "Failed to fetch playlist file while probing remote!",
+    )
+    .await?
+    .error_for_status()
+    .context("Remote refused to serve the playlist file!")?
+    .text()
+    .await
+    .context("Failed to read contents of playlist file!")?;
+
+    let segment_url = rebase(
+        first_segment(&playlist).context("Playlist file does not contain any video chunks!")?,
+        base,
+    );
+
+    let start = Instant::now();
+
+    let segment = tokio::time::timeout(PROBE_TIMEOUT, async {
+        get(&segment_url, id_token, "Failed to fetch video chunk!")
+            .await?
+            .error_for_status()
+            .context("Remote refused to serve the video chunk!")?
+            .bytes()
+            .await
+            .context("Failed to read video chunk!")
+    })
+    .await
+    .context(format!(
+        "Remote {base} took too long to send a video chunk!"
+    ))??;
+
+    let elapsed = start.elapsed().as_secs_f64().max(f64::EPSILON);
+
+    Ok(segment.len() as f64 / elapsed)
+}
+
+/// Measures the throughput of every remote, re-using the previous measurement if it was
+/// taken less than [`REEVALUATE_INTERVAL`] ago and still has remotes that aren't cooling down
+#[instrument(skip(id_token))]
+pub async fn measure(address: &str, id_token: &Arc<Token>) -> Result<Vec<RemoteThroughput>> {
+    let mut measurement = MEASUREMENT.lock().await;
+
+    if let Some(previous) = measurement.as_ref() {
+        let elapsed = previous.measured_at.elapsed();
+        let available = previous.available();
+
+        if elapsed < REEVALUATE_INTERVAL && !available.is_empty() {
+            info!("Using remote measurement from {elapsed:?} ago");
+            return Ok(available);
+        }
+    }
+
+    info!("Probing throughput of all remotes");
+
+    let mut set = JoinSet::new();
+    for &base in REMOTES.iter() {
+        let (address, id_token) = (address.to_string(), id_token.clone());
+        set.spawn(async move { (base, probe(base, &address, &id_token).await) });
+    }
+
+    let mut remotes = Vec::with_capacity(REMOTES.len());
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((base, Ok(bytes_per_sec))) => {
+                info!(
+                    "Remote {base} served a chunk at {:.1} KiB/s",
+                    bytes_per_sec / 1024.0
+                );
+                remotes.push(RemoteThroughput {
+                    base,
+                    bytes_per_sec,
+                });
+            }
+
+            Ok((base, Err(err))) => warn!("Remote {base} failed its throughput probe: {err}"),
+
+            Err(err) => error!("Throughput probe task failed: {err}"),
+        }
+    }
+
+    if remotes.is_empty() {
+        return Err(anyhow::Error::msg(
+            "Failed to connect to any available download sources! Check your connection and try again.",
+        ));
+    }
+
+    remotes.sort_by(|a, b| b.bytes_per_sec.total_cmp(&a.bytes_per_sec));
+
+    // Remotes that just served their probe are trusted again, even if they failed recently
+    *measurement = Some(Measurement {
+        remotes: remotes.clone(),
+        measured_at: Instant::now(),
+        failures: HashMap::new(),
+    });
+
+    Ok(remotes)
+}
+
+/// The fastest of the measured remotes
+fn fastest(remotes: &[RemoteThroughput]) -> Result<&'static str> {
+    remotes
+        .first()
+        .map(|remote| remote.base)
+        .context("No download sources are available! Check your connection and try again.")
+}
+
+/// Picks the remote with the highest measured throughput
+pub async fn select_base(address: &str, id_token: &Arc<Token>) -> Result<&'static str> {
+    let base = fastest(&measure(address, id_token).await?)?;
+    info!("Fastest remote is {base}");
+    Ok(base)
+}
+
+/// Passes over a remote for [`COOLDOWN`], so later lectures don't select it while it's failing
+pub async fn invalidate(base: &str) {
+    if let Some(measurement) = MEASUREMENT.lock().await.as_mut() {
+        warn!("Passing over remote {base} for {COOLDOWN:?}");
+        measurement
+            .failures
+            .insert(base.to_string(), Instant::now());
+    }
+}
+
+/// Remotes to spread chunk downloads across, starting with `base`
+pub async fn stripe(base: &str) -> Vec<String> {
+    let mut bases = vec![base.to_string()];
+
+    if let Some(measurement) = MEASUREMENT.lock().await.as_ref() {
+        let remotes = measurement.available();
+        if let Some(fastest) = remotes.first() {
+            bases.extend(
+                remotes
+                    .iter()
+                    .filter(|remote| {
+                        remote.base != base
+                            && remote.bytes_per_sec >= fastest.bytes_per_sec * STRIPE_THRESHOLD
+                    })
+                    .map(|remote| remote.base.to_string()),
+            );
+        }
+    }
+
+    bases
+}
+
+/// Remotes that can take over once the remotes a lecture is being downloaded from have failed
+pub struct Failover {
+    /// Remotes that haven't been tried yet, in the order they should be tried
+    fallbacks: Vec<String>,
+}
+
+impl Failover {
+    pub async fn new(bases: &[String]) -> Self {
+        // Prefer the fastest measured remotes, then any remote that hasn't been measured, and
+        // only then the ones that failed recently
+        let mut fallbacks: Vec<String> = match MEASUREMENT.lock().await.as_ref() {
+            Some(measurement) => {
+                let (cooling, mut fallbacks): (Vec<_>, Vec<_>) = measurement
+                    .remotes
+                    .iter()
+                    .map(|remote| remote.base.to_string())
+                    .partition(|base| measurement.cooling_down(base));
+
+                for remote in REMOTES.iter() {
+                    let remote = remote.to_string();
+                    if !fallbacks.contains(&remote) && !cooling.contains(&remote) {
+                        fallbacks.push(remote);
+                    }
+                }
+                fallbacks.extend(cooling);
+                fallbacks
+            }
+            None => vec![],
+        };
+
+        for remote in REMOTES.iter() {
+            if !fallbacks.iter().any(|fallback| fallback == remote) {
+                fallbacks.push(remote.to_string());
+            }
+        }
+
+        fallbacks.retain(|fallback| !bases.contains(fallback));
+
+        Self { fallbacks }
+    }
+
+    /// The next remote to try, if any are left
+    pub fn next(&mut self) -> Option<String> {
+        if self.fallbacks.is_empty() {
+            return None;
+        }
+
+        Some(self.fallbacks.remove(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurement(failures: &[(&str, Duration)]) -> Measurement {
+        Measurement {
+            remotes: vec![
+                RemoteThroughput {
+                    base: "https://fast",
+                    bytes_per_sec: 2048.0,
+                },
+                RemoteThroughput {
+                    base: "https://slow",
+                    bytes_per_sec: 1024.0,
+                },
+            ],
+            measured_at: Instant::now(),
+            failures: failures
+                .iter()
+                .map(|&(base, ago)| (base.to_string(), Instant::now() - ago))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn fastest_of_nothing_is_an_error() {
+        assert!(fastest(&[]).is_err());
+    }
+
+    #[test]
+    fn fastest_is_first() {
+        assert_eq!(fastest(&measurement(&[]).remotes).unwrap(), "https://fast");
+    }
+
+    #[test]
+    fn failed_remotes_cool_down() {
+        let measurement = measurement(&[("https://fast", Duration::ZERO)]);
+        let available = measurement.available();
+
+        assert_eq!(available.len(), 1);
+        assert_eq!(available[0].base, "https://slow");
+    }
+
+    #[test]
+    fn failed_remotes_are_trusted_again_after_cooling_down() {
+        let measurement = measurement(&[("https://fast", COOLDOWN + Duration::from_secs(1))]);
+        assert_eq!(measurement.available().len(), 2);
+    }
+
+    #[test]
+    fn nothing_is_available_once_every_remote_failed() {
+        let measurement = measurement(&[
+            ("https://fast", Duration::ZERO),
+            ("https://slow", Duration::ZERO),
+        ]);
+        assert!(measurement.available().is_empty());
+        assert!(fastest(&measurement.available()).is_err());
+    }
+
+    #[test]
+    fn rebases_fetchvideo_urls() {
+        let url = "https://a.example/api/fetchvideo?tag=LC&inm3u8=x";
+        assert_eq!(
+            rebase(url, "http://b.example"),
+            "http://b.example/api/fetchvideo?tag=LC&inm3u8=x"
+        );
+        assert_eq!(remote_of(url), "https://a.example");
+    }
+}
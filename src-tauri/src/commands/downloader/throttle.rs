@@ -0,0 +1,244 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::Timelike;
+
+use crate::{commands::schedule::in_window, prelude::*};
+
+/// A download speed limit for part of the day
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ScheduledLimit {
+    /// Hours (local time) this applies between, as in [`in_window`]
+    pub window: (u32, u32),
+    /// In KB/s, or no limit at all
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct BandwidthLimit {
+    /// In KB/s, shared by every download
+    #[serde(default)]
+    pub limit: Option<u32>,
+    /// Limits that take over from `limit` during their hours. The first matching one is used
+    #[serde(default)]
+    pub schedule: Vec<ScheduledLimit>,
+    /// Chunk requests a single remote is sent each second
+    #[serde(default)]
+    pub requests_per_sec: Option<f64>,
+}
+
+impl BandwidthLimit {
+    /// Rejects limits that would stall every download, and hours or windows that are never reached
+    pub fn validate(&self) -> Result<()> {
+        let mut limits = self
+            .limit
+            .into_iter()
+            .chain(self.schedule.iter().filter_map(|scheduled| scheduled.limit));
+
+        if limits.any(|limit| limit == 0) {
+            return Err(anyhow::Error::msg(
+                "A speed limit of 0 KB/s would never finish downloading! Leave it empty for no limit.",
+            ));
+        }
+
+        for hour in self
+            .schedule
+            .iter()
+            .flat_map(|scheduled| [scheduled.window.0, scheduled.window.1])
+        {
+            if hour > 23 {
+                return Err(anyhow::Error::msg(format!(
+                    "{hour} is not an hour of the day! Speed limit hours go from 0 to 23."
+                )));
+            }
+        }
+
+        if let Some(scheduled) = self
+            .schedule
+            .iter()
+            .find(|scheduled| scheduled.window.0 == scheduled.window.1)
+        {
+            return Err(anyhow::Error::msg(format!(
+                "The speed limit window from {0} to {0} never applies! It has to end at a different hour than it starts.",
+                scheduled.window.0
+            )));
+        }
+
+        if let Some(requests_per_sec) = self.requests_per_sec {
+            if !(requests_per_sec.is_finite() && requests_per_sec > 0.0) {
+                return Err(anyhow::Error::msg(
+                    "The request limit must be above 0! Leave it empty for no limit.",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The limit right now, in bytes per second
+    fn current(&self) -> Option<f64> {
+        let hour = chrono::Local::now().hour();
+
+        self.schedule
+            .iter()
+            .find(|scheduled| in_window(hour, scheduled.window))
+            .map_or(self.limit, |scheduled| scheduled.limit)
+            .map(|limit| limit as f64 * 1000.0)
+    }
+}
+
+static CONFIG: LazyLock<Mutex<BandwidthLimit>> =
+    LazyLock::new(|| Mutex::new(BandwidthLimit::default()));
+
+/// Bytes that may be downloaded before waiting. Goes negative when downloads get ahead of the
+/// limit, and each of them then waits for its share of that to pay off
+struct Bucket {
+    allowance: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    /// Takes `bytes` out of the allowance, refilled at `rate` bytes per second, and returns how
+    /// long to wait before downloading them
+    fn take(&mut self, bytes: usize, rate: f64, now: Instant) -> Duration {
+        // Up to a second's worth can be saved up, so short bursts aren't slowed down
+        self.allowance =
+            (self.allowance + now.duration_since(self.refilled).as_secs_f64() * rate).min(rate);
+        self.refilled = now;
+        self.allowance -= bytes as f64;
+
+        // Nothing is owed while the allowance is positive
+        Duration::try_from_secs_f64(-self.allowance / rate).unwrap_or_default()
+    }
+}
+
+static BUCKET: LazyLock<Mutex<Bucket>> = LazyLock::new(|| {
+    Mutex::new(Bucket {
+        allowance: 0.0,
+        refilled: Instant::now(),
+    })
+});
+
+/// When the next request may be sent to each remote
+static NEXT_REQUEST: LazyLock<Mutex<HashMap<String, Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Sets the limits for the downloads that follow
+pub fn configure(limit: BandwidthLimit) {
+    *CONFIG.lock().unwrap() = limit;
+}
+
+/// Waits until `bytes` more may be downloaded without going over the speed limit
+pub async fn consume(bytes: usize) {
+    // Zero is rejected when saving settings, and would otherwise never let anything through
+    let Some(rate) = CONFIG.lock().unwrap().current().filter(|rate| *rate > 0.0) else {
+        return;
+    };
+
+    let wait = BUCKET.lock().unwrap().take(bytes, rate, Instant::now());
+
+    if !wait.is_zero() {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Waits until another request may be sent to the remote
+pub async fn request(remote: &str) {
+    let Some(requests_per_sec) = CONFIG.lock().unwrap().requests_per_sec else {
+        return;
+    };
+
+    let wait = {
+        let mut next_request = NEXT_REQUEST.lock().unwrap();
+        let now = Instant::now();
+        let slot = next_request.entry(remote.to_string()).or_insert(now);
+
+        // Take the next free slot, and leave the one after it for whoever asks next
+        let at = (*slot).max(now);
+        *slot = at + Duration::from_secs_f64(1.0 / requests_per_sec.max(0.01));
+
+        at - now
+    };
+
+    if !wait.is_zero() {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(allowance: f64, refilled: Instant) -> Bucket {
+        Bucket {
+            allowance,
+            refilled,
+        }
+    }
+
+    #[test]
+    fn bursts_within_the_allowance_go_through() {
+        let now = Instant::now();
+        let mut bucket = bucket(1000.0, now);
+
+        assert_eq!(bucket.take(600, 1000.0, now), Duration::ZERO);
+        assert_eq!(bucket.take(400, 1000.0, now), Duration::ZERO);
+    }
+
+    #[test]
+    fn going_over_waits_for_the_debt() {
+        let now = Instant::now();
+        let mut bucket = bucket(0.0, now);
+
+        assert_eq!(bucket.take(500, 1000.0, now), Duration::from_millis(500));
+        // Waits stack up for whoever comes after
+        assert_eq!(bucket.take(500, 1000.0, now), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn refills_over_time_up_to_a_second() {
+        let start = Instant::now();
+        let mut bucket = bucket(-1000.0, start);
+
+        // One second pays off the debt
+        let later = start + Duration::from_secs(1);
+        assert_eq!(bucket.take(0, 1000.0, later), Duration::ZERO);
+
+        // A long idle period only saves up a second's worth
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(
+            bucket.take(1500, 1000.0, much_later),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn rejects_limits_that_stall_downloads() {
+        let limit = |limit, schedule, requests_per_sec| BandwidthLimit {
+            limit,
+            schedule,
+            requests_per_sec,
+        };
+        let scheduled = |window, limit| vec![ScheduledLimit { window, limit }];
+
+        assert!(limit(None, vec![], None).validate().is_ok());
+        assert!(limit(Some(100), scheduled((22, 6), None), Some(2.0))
+            .validate()
+            .is_ok());
+
+        assert!(limit(Some(0), vec![], None).validate().is_err());
+        assert!(limit(None, scheduled((22, 6), Some(0)), None)
+            .validate()
+            .is_err());
+        assert!(limit(None, scheduled((22, 24), None), None)
+            .validate()
+            .is_err());
+        assert!(limit(None, scheduled((9, 9), Some(100)), None)
+            .validate()
+            .is_err());
+        assert!(limit(None, vec![], Some(0.0)).validate().is_err());
+    }
+}
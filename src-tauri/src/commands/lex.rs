@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use crate::prelude::*;
+
+use serde::de::DeserializeOwned;
+use tauri_plugin_http::reqwest::Url;
+
+use super::{
+    auth::Token,
+    downloader::{get, BASE},
+    Video,
+};
+
+/// A SurrealDB record id, as returned by Lex
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct RecordId<T> {
+    #[serde(rename = "Table")]
+    pub table: String,
+    #[serde(rename = "ID")]
+    pub id: T,
+}
+
+/// `(department, code)`, eg. `("CS", "F111")`
+pub type SubjectId = (String, String);
+
+/// `(impartus_session, impartus_subject)`
+pub type LectureId = (i64, i64);
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Subject {
+    pub id: RecordId<SubjectId>,
+    pub department: String,
+    pub code: String,
+    pub name: String,
+}
+
+/// A section of a subject in a particular session, which holds its videos
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Lecture {
+    pub id: RecordId<LectureId>,
+    pub impartus_session: i64,
+    pub impartus_subject: i64,
+    pub section: String,
+    pub professor: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LectureVideo {
+    pub ttid: i32,
+    pub topic: String,
+    pub start_time: String,
+}
+
+/// Maps a session id to its `(year, semester)`
+pub type Sessions = HashMap<String, (i32, i32)>;
+
+/// GETs and parses a Lex Impartus API endpoint
+async fn fetch<T: DeserializeOwned>(token: &Token, url: Url) -> Result<T> {
+    let res = get(url.as_str(), token, "Failed to fetch data from Lex!")
+        .await?
+        .error_for_status()
+        .context(format!("Lex refused to serve \"{url}\"!"))?
+        .bytes()
+        .await
+        .context("Failed to read response from Lex!")?;
+
+    parse(&res).context(format!("Failed to parse response from \"{url}\"!"))
+}
+
+fn parse<T: DeserializeOwned>(res: &[u8]) -> Result<T> {
+    Ok(serde_json::from_slice(res)?)
+}
+
+fn url(path: &str) -> Result<Url> {
+    Url::parse(&format!("{BASE}/impartus/{path}")).context("Failed to build Lex url!")
+}
+
+#[instrument(skip(token))]
+pub async fn search_subjects(token: &Token, query: &str) -> Result<Vec<Subject>> {
+    let mut url = url("subject/search")?;
+    url.query_pairs_mut().append_pair("q", query);
+    fetch(token, url).await
+}
+
+#[instrument(skip(token))]
+pub async fn sessions(token: &Token) -> Result<Sessions> {
+    fetch(token, url("session")?).await
+}
+
+#[instrument(skip(token))]
+pub async fn lectures(token: &Token, (department, code): &SubjectId) -> Result<Vec<Lecture>> {
+    // Departments like "CS/IS" are shared, and Lex expects the slash as a comma
+    let department = department.replace('/', ",");
+    fetch(
+        token,
+        url(&format!("subject/{department}/{code}/lectures"))?,
+    )
+    .await
+}
+
+/// Current metadata of a lecture of a subject, which fails if Lex no longer lists it
+#[instrument(skip(token))]
+pub async fn lecture(token: &Token, subject: &SubjectId, id: &LectureId) -> Result<Lecture> {
+    lectures(token, subject)
+        .await?
+        .into_iter()
+        .find(|lecture| lecture.id.id == *id)
+        .context(format!("Lex no longer lists the lecture {id:?}!"))
+}
+
+/// Videos of a lecture, newest first
+#[instrument(skip(token))]
+pub async fn videos(token: &Token, (session, subject): &LectureId) -> Result<Vec<LectureVideo>> {
+    fetch(token, url(&format!("lecture/{session}/{subject}"))?).await
+}
+
+/// Formats an ISO-8601 timestamp the way the frontend displays dates (`d/m/yyyy`), so that
+/// `{date}` in the filename format matches what it would be for a download started from the UI
+fn format_date(start_time: &str) -> String {
+    let mut parts = start_time
+        .get(..10)
+        .unwrap_or(start_time)
+        .split('-')
+        .map(|part| part.parse::<u32>());
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) => format!("{day}/{month}/{year}"),
+        _ => start_time.to_string(),
+    }
+}
+
+/// Resolves every video of a lecture into the downloadable form the `download` command takes
+#[instrument(skip(token, subject, lecture), fields(subject = subject.name, section = lecture.section))]
+pub async fn subject_videos(
+    token: &Token,
+    subject: &Subject,
+    lecture: &Lecture,
+) -> Result<Vec<Video>> {
+    let videos = videos(token, &lecture.id.id).await?;
+    let count = videos.len() as i32;
+
+    info!("Resolved {count} videos");
+
+    Ok(videos
+        .into_iter()
+        .enumerate()
+        .map(|(i, video)| Video {
+            ttid: video.ttid,
+            topic: video.topic,
+            subject_name: subject.name.clone(),
+            // Videos are listed newest first, and numbered oldest first
+            number: count - i as i32,
+            start_time: format_date(&video.start_time),
+            replace: None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_date, parse, Lecture, LectureVideo, Sessions, Subject};
+
+    #[test]
+    fn formats_like_the_frontend() {
+        assert_eq!(format_date("2025-01-09T10:30:00+05:30"), "9/1/2025");
+        assert_eq!(format_date("2024-12-31"), "31/12/2024");
+    }
+
+    #[test]
+    fn keeps_unparseable_dates() {
+        assert_eq!(format_date("yesterday"), "yesterday");
+        assert_eq!(format_date(""), "");
+        assert_eq!(format_date("2025-0"), "2025-0");
+    }
+
+    #[test]
+    fn parses_subjects() {
+        let subjects: Vec<Subject> = parse(
+            br#"[{"id":{"Table":"subject","ID":["CS/IS","F111"]},"department":"CS/IS","code":"F111","name":"Computer Programming"}]"#,
+        )
+        .unwrap();
+
+        assert_eq!(subjects.len(), 1);
+        assert_eq!(subjects[0].id.table, "subject");
+        assert_eq!(subjects[0].id.id, ("CS/IS".into(), "F111".into()));
+        assert_eq!(subjects[0].name, "Computer Programming");
+    }
+
+    #[test]
+    fn parses_sessions() {
+        let sessions: Sessions = parse(br#"{"1191":[2024,1],"1192":[2024,2]}"#).unwrap();
+
+        assert_eq!(sessions["1191"], (2024, 1));
+        assert_eq!(sessions["1192"], (2024, 2));
+    }
+
+    #[test]
+    fn parses_lectures() {
+        let lectures: Vec<Lecture> = parse(
+            br#"[{"id":{"Table":"lecture","ID":[1191,3456]},"impartus_session":1191,"impartus_subject":3456,"section":"L1","professor":"Someone"}]"#,
+        )
+        .unwrap();
+
+        assert_eq!(lectures[0].id.id, (1191, 3456));
+        assert_eq!(lectures[0].section, "L1");
+    }
+
+    #[test]
+    fn parses_videos() {
+        let videos: Vec<LectureVideo> = parse(
+            br#"[{"ttid":42,"topic":"Pointers","startTime":"2025-01-09T10:30:00+05:30","extra":true}]"#,
+        )
+        .unwrap();
+
+        assert_eq!(videos[0].ttid, 42);
+        assert_eq!(videos[0].start_time, "2025-01-09T10:30:00+05:30");
+    }
+
+    #[test]
+    fn rejects_malformed_responses() {
+        assert!(parse::<Vec<Subject>>(br#"{"error":"unauthorized"}"#).is_err());
+        assert!(parse::<Sessions>(br#"{"1191":"2024"}"#).is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use crate::prelude::*;
+
+use chrono::{DateTime, Local};
+use sha2::{Digest, Sha256};
+use tauri::AppHandle;
+use tauri_plugin_shell::{process::CommandEvent, ShellExt};
+use tokio::sync::Mutex;
+
+use super::{
+    downloader::{Resolution, Views},
+    partial,
+};
+
+pub mod archive;
+pub mod export;
+
+// Downloads finish concurrently, and each of them rewrites the whole index
+static LIBRARY_LOCK: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
+
+/// How far, in seconds, a file's duration may drift from its playlist's before it's flagged
+const DURATION_TOLERANCE: f64 = 5.0;
+
+/// A lecture that has been downloaded into the library
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryEntry {
+    pub ttid: i32,
+    pub subject: String,
+    pub number: i32,
+    pub topic: String,
+    pub date: String,
+    pub resolution: Resolution,
+    pub views: Views,
+    pub path: String,
+    /// In bytes
+    pub size: u64,
+    /// In seconds, as listed in the lecture's playlist
+    pub duration: f64,
+    /// SHA-256 of the file
+    pub checksum: String,
+    pub downloaded_at: DateTime<Local>,
+    /// Bundle of the original chunks, when archiving is turned on
+    #[serde(default)]
+    pub bundle: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum EntryStatus {
+    Ok,
+    Missing,
+    SizeMismatch,
+    ChecksumMismatch,
+    /// The file is intact, but shorter or longer than its playlist, usually from a cut off mux
+    DurationMismatch,
+    /// The file is there, but couldn't be read to check it
+    Unreadable,
+    /// The file is intact, but its duration couldn't be probed to tell whether it's complete
+    Unverifiable,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifiedEntry {
+    pub entry: LibraryEntry,
+    pub status: EntryStatus,
+    /// In seconds, as reported by ffmpeg
+    pub actual_duration: Option<f64>,
+    /// Why the file couldn't be checked, when it is unreadable
+    pub error: Option<String>,
+}
+
+fn library_path(app_data: &Path) -> PathBuf {
+    app_data.join("library.json")
+}
+
+async fn read(app_data: &Path) -> Result<Vec<LibraryEntry>> {
+    let path = library_path(app_data);
+
+    if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+        return Ok(vec![]);
+    }
+
+    serde_json::from_slice(
+        &tokio::fs::read(path)
+            .await
+            .context("Failed to read library.json!")?,
+    )
+    .context("Failed to parse library.json!")
+}
+
+async fn write(app_data: &Path, entries: &[LibraryEntry]) -> Result<()> {
+    tokio::fs::create_dir_all(app_data)
+        .await
+        .context("Failed to create app data dir!")?;
+
+    partial::write(
+        &library_path(app_data),
+        serde_json::to_vec(entries).context("Failed to serialize library!")?,
+    )
+    .await
+    .context("Failed to write library.json!")
+}
+
+pub async fn load(app_data: &Path) -> Result<Vec<LibraryEntry>> {
+    let _lock = LIBRARY_LOCK.lock().await;
+    read(app_data).await
+}
+
+/// Adds an entry to the library, replacing any earlier entry for the same file
+#[instrument(skip_all, fields(ttid = entry.ttid, path = entry.path))]
+pub async fn record(app_data: &Path, entry: LibraryEntry) -> Result<()> {
+    let _lock = LIBRARY_LOCK.lock().await;
+
+    let mut entries = read(app_data).await?;
+    entries.retain(|existing| existing.path != entry.path);
+    entries.push(entry);
+
+    write(app_data, &entries).await?;
+
+    info!("Recorded lecture in library");
+
+    Ok(())
+}
+
+/// Computes the SHA-256 of a file without reading all of it into memory
+pub async fn checksum(path: &Path) -> Result<String> {
+    let path = path.to_path_buf();
+
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path).context("Failed to open file for hashing!")?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 1 << 20];
+
+        loop {
+            let read = file
+                .read(&mut buf)
+                .context("Failed to read file for hashing!")?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+        }
+
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await
+    .context("Hashing task failed!")?
+}
+
+/// Entries whose subject, topic, date or number contain the query, ignoring case
+pub fn search(entries: Vec<LibraryEntry>, query: &str) -> Vec<LibraryEntry> {
+    let query = query.to_lowercase();
+
+    entries
+        .into_iter()
+        .filter(|entry| {
+            entry.subject.to_lowercase().contains(&query)
+                || entry.topic.to_lowercase().contains(&query)
+                || entry.date.contains(&query)
+                || entry.number.to_string() == query
+        })
+        .collect()
+}
+
+/// Reads a file's duration in seconds from the header ffmpeg prints when given only an input
+#[instrument(skip(app))]
+pub async fn probe_duration(app: &AppHandle, path: &Path) -> Result<f64> {
+    let path = path.to_str().context("File path is not valid UTF-8!")?;
+
+    let (mut rx, _child) = app
+        .shell()
+        .sidecar("multipartus-ffmpeg")
+        .context("ffmpeg command create")?
+        .args(["-hide_banner", "-nostdin", "-i", path])
+        .spawn()
+        .context("spawn ffmpeg")?;
+
+    let mut output = String::new();
+    while let Some(event) = rx.recv().await {
+        if let CommandEvent::Stderr(bytes) | CommandEvent::Stdout(bytes) = event {
+            output.push_str(&String::from_utf8_lossy(&bytes));
+            output.push('\n');
+        }
+    }
+
+    parse_duration(&output).with_context(|| format!("No duration in ffmpeg output: {output}"))
+}
+
+/// Parses "Duration: 01:23:45.67" out of ffmpeg's output
+fn parse_duration(output: &str) -> Option<f64> {
+    let (_, rest) = output.split_once("Duration: ")?;
+    let timestamp = rest.split(',').next()?.trim();
+
+    let mut seconds = 0.0;
+    for part in timestamp.split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+
+    Some(seconds)
+}
+
+/// Checks that an entry's file is still there, unchanged since it was downloaded, and as long
+/// as its playlist says it should be
+pub async fn verify_entry(app: &AppHandle, entry: LibraryEntry) -> VerifiedEntry {
+    let verified = |status, actual_duration, error| VerifiedEntry {
+        entry: entry.clone(),
+        status,
+        actual_duration,
+        error,
+    };
+    let path = Path::new(&entry.path);
+
+    let metadata = match tokio::fs::metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return verified(EntryStatus::Missing, None, None)
+        }
+        Err(e) => return verified(EntryStatus::Unreadable, None, Some(e.to_string())),
+    };
+
+    if metadata.len() != entry.size {
+        return verified(EntryStatus::SizeMismatch, None, None);
+    }
+
+    match checksum(path).await {
+        Ok(checksum) if checksum == entry.checksum => (),
+        Ok(_) => return verified(EntryStatus::ChecksumMismatch, None, None),
+        Err(e) => return verified(EntryStatus::Unreadable, None, Some(format!("{e:#}"))),
+    }
+
+    match probe_duration(app, path).await {
+        Ok(duration) if (duration - entry.duration).abs() > DURATION_TOLERANCE => {
+            verified(EntryStatus::DurationMismatch, Some(duration), None)
+        }
+        Ok(duration) => verified(EntryStatus::Ok, Some(duration), None),
+        // Not being able to probe the file doesn't mean it's broken, but it isn't known to be fine
+        Err(e) => {
+            warn!("Failed to probe duration of `{}`: {e}", entry.path);
+            verified(EntryStatus::Unverifiable, None, Some(format!("{e:#}")))
+        }
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn verify(app: &AppHandle, app_data: &Path) -> Result<Vec<VerifiedEntry>> {
+    let entries = load(app_data).await?;
+    let mut verified = Vec::with_capacity(entries.len());
+
+    for entry in entries {
+        let checked = verify_entry(app, entry).await;
+        match (&checked.status, &checked.error) {
+            (EntryStatus::Ok, _) => (),
+            (status, Some(e)) => warn!(
+                "Lecture {} at `{}` is {status:?}: {e}",
+                checked.entry.ttid, checked.entry.path
+            ),
+            (status, None) => warn!(
+                "Lecture {} at `{}` is {status:?}",
+                checked.entry.ttid, checked.entry.path
+            ),
+        }
+        verified.push(checked);
+    }
+
+    Ok(verified)
+}
+
+/// Points an entry at a file that was moved, as long as it is the same file
+#[instrument(skip(app_data))]
+pub async fn relink(app_data: &Path, path: &str, new_path: &Path) -> Result<LibraryEntry> {
+    let _lock = LIBRARY_LOCK.lock().await;
+
+    let mut entries = read(app_data).await?;
+    let entry = entries
+        .iter_mut()
+        .find(|entry| entry.path == path)
+        .context("No such lecture in the library!")?;
+
+    let size = tokio::fs::metadata(new_path)
+        .await
+        .context("Failed to read the new file!")?
+        .len();
+
+    if size != entry.size || checksum(new_path).await? != entry.checksum {
+        return Err(anyhow::Error::msg(format!(
+            "`{}` is not the same file as `{path}`!",
+            new_path.display()
+        )));
+    }
+
+    entry.path = new_path.to_string_lossy().to_string();
+    let entry = entry.clone();
+
+    write(app_data, &entries).await?;
+
+    info!("Relinked lecture {} to `{}`", entry.ttid, entry.path);
+
+    Ok(entry)
+}
+
+/// The entries recorded for the given files
+pub async fn find(app_data: &Path, paths: &[String]) -> Result<Vec<LibraryEntry>> {
+    Ok(load(app_data)
+        .await?
+        .into_iter()
+        .filter(|entry| paths.contains(&entry.path))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_duration_from_ffmpeg_header() {
+        let output = "Input #0, mov,mp4,m4a,3gp,3g2,mj2, from 'lecture.mp4':\n  \
+            Duration: 01:23:45.67, start: 0.000000, bitrate: 512 kb/s\n";
+
+        let duration = parse_duration(output).unwrap();
+        assert!((duration - 5025.67).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parses_short_durations() {
+        assert_eq!(parse_duration("Duration: 00:00:07.00, start"), Some(7.0));
+    }
+
+    #[test]
+    fn rejects_missing_or_unknown_durations() {
+        assert_eq!(parse_duration("no header here"), None);
+        assert_eq!(parse_duration("Duration: N/A, start: 0.000000"), None);
+    }
+}
@@ -0,0 +1,255 @@
+use std::path::{Path, PathBuf};
+
+use crate::prelude::*;
+
+use tauri::{AppHandle, Manager};
+use tauri_plugin_shell::{
+    process::{CommandEvent, TerminatedPayload},
+    ShellExt,
+};
+
+use super::super::{ffmpeg_args, partial};
+
+/// Bundles are saved next to their lecture, as `<lecture>.lecture.tar`
+pub const EXTENSION: &str = "lecture.tar";
+
+const SIDE_1: &str = "side_1.m3u8";
+const SIDE_2: &str = "side_2.m3u8";
+const KEY: &str = "key.key";
+const CHUNKS: &str = "ts_store";
+
+/// Points a cached playlist at the bundle's copies of its key and chunks, which are relative
+/// to the playlist so the bundle can be extracted anywhere. Returns the playlist and its chunks
+fn rewrite_playlist(playlist: &str) -> (String, Vec<PathBuf>) {
+    let mut out = String::with_capacity(playlist.len());
+    let mut chunks = vec![];
+
+    for line in playlist.lines() {
+        if let Some((method, _)) = line
+            .strip_prefix("#EXT-X-KEY:")
+            .and_then(|key| key.split_once(','))
+        {
+            out += &format!("#EXT-X-KEY:{method},URI=\"{KEY}\"");
+        } else if !line.starts_with('#') && !line.is_empty() {
+            let chunk = PathBuf::from(line);
+            let name = chunk
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            out += &format!("{CHUNKS}/{name}");
+            chunks.push(chunk);
+        } else {
+            out += line;
+        }
+        out.push('\n');
+    }
+
+    (out, chunks)
+}
+
+/// Packs a lecture's playlists, key and encrypted chunks from the cache into a bundle
+#[instrument(skip(side1, side2, key))]
+pub async fn pack(side1: &str, side2: Option<&str>, key: &str, bundle: &Path) -> Result<()> {
+    let sides = [Some((SIDE_1, side1)), side2.map(|side2| (SIDE_2, side2))]
+        .into_iter()
+        .flatten()
+        .map(|(name, path)| (name, PathBuf::from(path)))
+        .collect::<Vec<_>>();
+    let key = PathBuf::from(key);
+    let partial = partial::path_for(bundle);
+
+    tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::create(&partial).context("Failed to create bundle!")?;
+        let mut builder = tar::Builder::new(std::io::BufWriter::new(file));
+
+        for (name, path) in sides {
+            let playlist = std::fs::read_to_string(&path).context("Failed to read playlist!")?;
+            let (playlist, chunks) = rewrite_playlist(&playlist);
+
+            let mut header = tar::Header::new_gnu();
+            header.set_size(playlist.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, playlist.as_bytes())
+                .context("Failed to add playlist to bundle!")?;
+
+            for chunk in chunks {
+                let name = Path::new(CHUNKS).join(chunk.file_name().unwrap_or_default());
+                builder
+                    .append_path_with_name(&chunk, name)
+                    .with_context(|| format!("Failed to add `{}` to bundle!", chunk.display()))?;
+            }
+        }
+
+        builder
+            .append_path_with_name(&key, KEY)
+            .context("Failed to add key to bundle!")?;
+
+        builder
+            .into_inner()
+            .context("Failed to finish bundle!")?
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .context("Failed to flush bundle!")?;
+
+        Ok::<_, anyhow::Error>(())
+    })
+    .await
+    .context("Archiving task failed!")??;
+
+    partial::finish(bundle).await?;
+
+    info!("Archived lecture to `{}`", bundle.display());
+
+    Ok(())
+}
+
+/// Extracts a bundle into the cache and muxes it into `output`, whose extension decides the
+/// format
+#[instrument(skip(app, cache))]
+pub async fn remux(app: &AppHandle, bundle: &Path, cache: &Path, output: &Path) -> Result<()> {
+    if tokio::fs::try_exists(output).await.unwrap_or(false) {
+        return Err(anyhow::Error::msg(format!(
+            "The file at `{}` already exists!",
+            output.display()
+        )));
+    }
+
+    let stem = bundle
+        .file_name()
+        .context("Bundle path has no file name!")?
+        .to_string_lossy()
+        .replace('.', "_");
+    let extracted = cache.join(format!("Remux_{stem}"));
+
+    let (from, to) = (bundle.to_path_buf(), extracted.clone());
+    tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(from).context("Failed to open bundle!")?;
+        tar::Archive::new(std::io::BufReader::new(file))
+            .unpack(to)
+            .context("Failed to extract bundle!")
+    })
+    .await
+    .context("Extraction task failed!")??;
+
+    let result = mux(app, &extracted, output).await;
+
+    let _ = tokio::fs::remove_dir_all(&extracted)
+        .await
+        .inspect_err(|e| warn!("Failed to remove extracted bundle: {e}"));
+
+    result
+}
+
+async fn mux(app: &AppHandle, extracted: &Path, output: &Path) -> Result<()> {
+    let side1 = extracted.join(SIDE_1);
+    let side2 = extracted.join(SIDE_2);
+
+    let side1 = side1.to_str().context("Cache path is not valid UTF-8!")?;
+    let side2 = if tokio::fs::try_exists(&side2).await.unwrap_or(false) {
+        Some(side2.to_str().context("Cache path is not valid UTF-8!")?)
+    } else {
+        None
+    };
+
+    let app_data = app.path().app_data_dir().context("reading app data dir")?;
+    let partial = partial::path_for(output);
+    let partial_str = partial
+        .to_str()
+        .context("Output path is not valid UTF-8!")?;
+
+    partial::begin(&app_data, &partial).await?;
+
+    let (mut rx, _child) = app
+        .shell()
+        .sidecar("multipartus-ffmpeg")
+        .context("ffmpeg command create")?
+        .args(ffmpeg_args(side1, side2, partial_str))
+        .spawn()
+        .context("spawn ffmpeg")?;
+
+    let mut ffmpeg_errors = String::new();
+    while let Some(event) = rx.recv().await {
+        match event {
+            CommandEvent::Stderr(bytes) => {
+                ffmpeg_errors.push_str(&String::from_utf8_lossy(&bytes));
+                ffmpeg_errors += "\n";
+            }
+            CommandEvent::Error(str) => {
+                ffmpeg_errors.push_str(&str);
+                ffmpeg_errors += "\n";
+            }
+            CommandEvent::Terminated(TerminatedPayload { code: Some(0), .. }) => {
+                ffmpeg_errors.clear();
+            }
+            _ => (),
+        }
+    }
+
+    if !ffmpeg_errors.is_empty() {
+        let _ = tokio::fs::remove_file(&partial).await;
+        let _ = partial::forget(&app_data, &partial).await;
+        return Err(anyhow::Error::msg(ffmpeg_errors));
+    }
+
+    partial::commit(&app_data, output, false).await?;
+
+    info!("Remuxed bundle into `{}`", output.display());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYLIST: &str = "#EXTM3U\n\
+        #EXT-X-TARGETDURATION:10\n\
+        #EXT-X-KEY:METHOD=AES-128,URI=\"/tmp/multipartus/42/key.key\"\n\
+        #EXTINF:10.0,\n\
+        /tmp/multipartus/42/ts_store/side_1_0.ts\n\
+        #EXTINF:4.5,\n\
+        /tmp/multipartus/42/ts_store/side_1_1.ts\n\
+        \n\
+        #EXT-X-ENDLIST\n";
+
+    #[test]
+    fn points_key_and_chunks_into_the_bundle() {
+        let (playlist, _) = rewrite_playlist(PLAYLIST);
+
+        assert_eq!(
+            playlist,
+            "#EXTM3U\n\
+            #EXT-X-TARGETDURATION:10\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"key.key\"\n\
+            #EXTINF:10.0,\n\
+            ts_store/side_1_0.ts\n\
+            #EXTINF:4.5,\n\
+            ts_store/side_1_1.ts\n\
+            \n\
+            #EXT-X-ENDLIST\n"
+        );
+    }
+
+    #[test]
+    fn lists_the_original_chunks_in_order() {
+        let (_, chunks) = rewrite_playlist(PLAYLIST);
+
+        assert_eq!(
+            chunks,
+            [
+                PathBuf::from("/tmp/multipartus/42/ts_store/side_1_0.ts"),
+                PathBuf::from("/tmp/multipartus/42/ts_store/side_1_1.ts"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_tags_without_a_key() {
+        let (playlist, chunks) = rewrite_playlist("#EXTM3U\n#EXT-X-ENDLIST\n");
+
+        assert_eq!(playlist, "#EXTM3U\n#EXT-X-ENDLIST\n");
+        assert!(chunks.is_empty());
+    }
+}
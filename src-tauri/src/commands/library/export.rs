@@ -0,0 +1,310 @@
+use std::path::{Path, PathBuf};
+
+use crate::prelude::*;
+
+use super::{super::remove_special, LibraryEntry};
+
+/// Writes an M3U and an XSPF playlist and an HTML index of a subject's lectures into the folder,
+/// returning the paths of the files written
+#[instrument(skip(entries))]
+pub async fn export(
+    mut entries: Vec<LibraryEntry>,
+    subject: &str,
+    folder: &Path,
+) -> Result<Vec<PathBuf>> {
+    entries.retain(|entry| entry.subject == subject);
+
+    if entries.is_empty() {
+        return Err(anyhow::Error::msg(format!(
+            "No lectures of `{subject}` have been downloaded!"
+        )));
+    }
+
+    entries.sort_by_key(|entry| entry.number);
+
+    tokio::fs::create_dir_all(folder)
+        .await
+        .context("Failed to create export folder!")?;
+
+    let name = remove_special(subject);
+    let outputs = [
+        (folder.join(format!("{name}.m3u")), m3u(&entries, folder)),
+        (
+            folder.join(format!("{name}.xspf")),
+            xspf(&entries, subject, folder),
+        ),
+        (
+            folder.join(format!("{name}.html")),
+            html(&entries, subject, folder),
+        ),
+    ];
+
+    let mut written = vec![];
+    for (path, contents) in outputs {
+        tokio::fs::write(&path, contents)
+            .await
+            .with_context(|| format!("Failed to write `{}`!", path.display()))?;
+        written.push(path);
+    }
+
+    info!("Exported {} lectures of {subject}", entries.len());
+
+    Ok(written)
+}
+
+/// The entry's path relative to the folder if it's inside it, so that the exports keep working
+/// when the whole folder is moved
+fn relative_path(entry: &LibraryEntry, folder: &Path) -> String {
+    let path = Path::new(&entry.path);
+    path.strip_prefix(folder)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// A URI to the entry, relative where possible
+fn uri(entry: &LibraryEntry, folder: &Path) -> String {
+    let path = relative_path(entry, folder);
+    let encoded = path
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect::<String>();
+
+    if Path::new(&path).is_absolute() {
+        // Windows paths need a leading slash after the scheme
+        let separator = if encoded.starts_with('/') { "" } else { "/" };
+        format!("file://{separator}{}", encoded.replacen("%3A", ":", 1))
+    } else {
+        encoded
+    }
+}
+
+fn title(entry: &LibraryEntry) -> String {
+    format!("{}. {}", entry.number, entry.topic)
+}
+
+/// Escapes text for both XML and HTML
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Formats seconds as H:MM:SS
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+fn m3u(entries: &[LibraryEntry], folder: &Path) -> String {
+    let mut out = String::from("#EXTM3U\n");
+
+    for entry in entries {
+        out += &format!(
+            "#EXTINF:{},{} - {}\n{}\n",
+            entry.duration.round() as u64,
+            entry.subject,
+            title(entry),
+            relative_path(entry, folder)
+        );
+    }
+
+    out
+}
+
+fn xspf(entries: &[LibraryEntry], subject: &str, folder: &Path) -> String {
+    let mut out = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <title>{}</title>\n  <trackList>\n",
+        escape(subject)
+    );
+
+    for entry in entries {
+        out += &format!(
+            "    <track>\n      <location>{}</location>\n      <title>{}</title>\n      <creator>{}</creator>\n      <trackNum>{}</trackNum>\n      <duration>{}</duration>\n      <annotation>{}</annotation>\n    </track>\n",
+            escape(&uri(entry, folder)),
+            escape(&title(entry)),
+            escape(&entry.subject),
+            entry.number,
+            (entry.duration * 1000.0).round() as u64,
+            escape(&entry.date)
+        );
+    }
+
+    out + "  </trackList>\n</playlist>\n"
+}
+
+fn html(entries: &[LibraryEntry], subject: &str, folder: &Path) -> String {
+    let subject = escape(subject);
+
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{subject}</title>\n<style>\nbody {{ font-family: sans-serif; margin: 2em; }}\ntable {{ border-collapse: collapse; }}\nth, td {{ padding: 0.4em 1em; border-bottom: 1px solid #ccc; text-align: left; }}\n</style>\n</head>\n<body>\n<h1>{subject}</h1>\n<table>\n<tr><th>#</th><th>Topic</th><th>Date</th><th>Duration</th></tr>\n"
+    );
+
+    for entry in entries {
+        out += &format!(
+            "<tr><td>{}</td><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            entry.number,
+            escape(&uri(entry, folder)),
+            escape(&entry.topic),
+            escape(&entry.date),
+            format_duration(entry.duration)
+        );
+    }
+
+    out + "</table>\n</body>\n</html>\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::downloader::{Resolution, Views};
+
+    fn entry(number: i32, topic: &str, path: &str) -> LibraryEntry {
+        LibraryEntry {
+            ttid: number,
+            subject: "CS F111 & Friends".into(),
+            number,
+            topic: topic.into(),
+            date: "9/1/2025".into(),
+            resolution: Resolution::HighRes,
+            views: Views {
+                left: true,
+                right: false,
+            },
+            path: path.into(),
+            size: 0,
+            duration: 3725.4,
+            checksum: String::new(),
+            downloaded_at: chrono::Local::now(),
+            bundle: None,
+        }
+    }
+
+    fn entries() -> Vec<LibraryEntry> {
+        vec![
+            entry(1, "Intro & C", "/lectures/CS F111/1. Intro & C.mp4"),
+            entry(
+                2,
+                "\"Pointers\" & <arrays>",
+                "/lectures/CS F111/2. \"Pointers\".mp4",
+            ),
+        ]
+    }
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            escape("a & b < c > d \"e\" 'f'"),
+            "a &amp; b &lt; c &gt; d &quot;e&quot; &apos;f&apos;"
+        );
+        // Already escaped text is escaped again, rather than passed through
+        assert_eq!(escape("&amp;"), "&amp;amp;");
+        assert_eq!(escape("Déjà vu λ"), "Déjà vu λ");
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(0.0), "0:00:00");
+        assert_eq!(format_duration(59.6), "0:01:00");
+        assert_eq!(format_duration(3725.4), "1:02:05");
+        assert_eq!(format_duration(36000.0), "10:00:00");
+    }
+
+    #[test]
+    fn encodes_relative_uris() {
+        let folder = Path::new("/lectures");
+
+        assert_eq!(
+            uri(&entry(1, "", "/lectures/CS F111/1. Intro & C.mp4"), folder),
+            "CS%20F111/1.%20Intro%20%26%20C.mp4"
+        );
+        assert_eq!(
+            uri(&entry(1, "", "/lectures/Déjà vu.mp4"), folder),
+            "D%C3%A9j%C3%A0%20vu.mp4"
+        );
+    }
+
+    #[test]
+    fn uses_forward_slashes_for_windows_separators() {
+        let entry = entry(1, "", "Lectures\\CS F111\\1.mp4");
+
+        assert_eq!(
+            relative_path(&entry, Path::new("/exports")),
+            "Lectures/CS F111/1.mp4"
+        );
+        assert_eq!(
+            uri(&entry, Path::new("/exports")),
+            "Lectures/CS%20F111/1.mp4"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_files_outside_the_folder_absolutely() {
+        assert_eq!(
+            uri(&entry(1, "", "/lectures/a b.mp4"), Path::new("/exports")),
+            "file:///lectures/a%20b.mp4"
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn links_files_outside_the_folder_absolutely() {
+        assert_eq!(
+            uri(
+                &entry(1, "", "C:\\Lectures\\a b.mp4"),
+                Path::new("D:\\exports")
+            ),
+            "file:///C:/Lectures/a%20b.mp4"
+        );
+    }
+
+    #[test]
+    fn writes_m3u() {
+        assert_eq!(
+            m3u(&entries(), Path::new("/lectures")),
+            "#EXTM3U\n\
+             #EXTINF:3725,CS F111 & Friends - 1. Intro & C\n\
+             CS F111/1. Intro & C.mp4\n\
+             #EXTINF:3725,CS F111 & Friends - 2. \"Pointers\" & <arrays>\n\
+             CS F111/2. \"Pointers\".mp4\n"
+        );
+    }
+
+    #[test]
+    fn writes_escaped_xspf() {
+        let xspf = xspf(&entries(), "CS F111 & Friends", Path::new("/lectures"));
+
+        assert!(xspf.contains("<title>CS F111 &amp; Friends</title>"));
+        assert!(xspf.contains("<location>CS%20F111/1.%20Intro%20%26%20C.mp4</location>"));
+        assert!(xspf.contains("<title>2. &quot;Pointers&quot; &amp; &lt;arrays&gt;</title>"));
+        assert!(xspf.contains("<creator>CS F111 &amp; Friends</creator>"));
+        assert!(xspf.contains("<duration>3725400</duration>"));
+        assert!(!xspf.contains("<arrays>"));
+        assert!(xspf.ends_with("</trackList>\n</playlist>\n"));
+    }
+
+    #[test]
+    fn writes_escaped_html() {
+        let html = html(&entries(), "CS F111 & Friends", Path::new("/lectures"));
+
+        assert!(html.contains("<title>CS F111 &amp; Friends</title>"));
+        assert!(html.contains("<h1>CS F111 &amp; Friends</h1>"));
+        assert!(html.contains(
+            "<tr><td>2</td><td><a href=\"CS%20F111/2.%20%22Pointers%22.mp4\">&quot;Pointers&quot; &amp; &lt;arrays&gt;</a></td><td>9/1/2025</td><td>1:02:05</td></tr>"
+        ));
+        assert!(!html.contains("<arrays>"));
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use crate::prelude::*;
+
+use tokio::sync::Mutex;
+
+/// Outputs that are still being written, so they can be cleaned up if the app dies midway
+const JOURNAL: &str = "partial_outputs.json";
+
+// Concurrent downloads add and remove their own outputs
+static JOURNAL_LOCK: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
+
+/// The sibling file an output is written to before being renamed into place once complete, so
+/// a crash midway leaves at most a stray partial file and never a truncated output where a
+/// finished one is expected. The extension is kept, since ffmpeg picks the container from it
+pub fn path_for(output: &Path) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match output.extension() {
+        Some(extension) => format!("{stem}.partial.{}", extension.to_string_lossy()),
+        None => format!("{stem}.partial"),
+    };
+    output.with_file_name(name)
+}
+
+/// Moves the finished partial of `output` into place, replacing whatever was there
+pub async fn finish(output: &Path) -> Result<()> {
+    tokio::fs::rename(path_for(output), output)
+        .await
+        .with_context(|| format!("Failed to move `{}` into place!", output.display()))
+}
+
+/// Writes all of `contents` to `output` through its partial
+pub async fn write(output: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    tokio::fs::write(path_for(output), contents)
+        .await
+        .with_context(|| format!("Failed to write `{}`!", output.display()))?;
+
+    finish(output).await
+}
+
+async fn read_journal(app_data: &Path) -> Result<Vec<PathBuf>> {
+    match tokio::fs::read_to_string(app_data.join(JOURNAL)).await {
+        Ok(contents) => serde_json::from_str(&contents).context("Failed to parse partial outputs!"),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).context("Failed to read partial outputs!"),
+    }
+}
+
+async fn write_journal(app_data: &Path, partials: &[PathBuf]) -> Result<()> {
+    tokio::fs::create_dir_all(app_data)
+        .await
+        .context("Failed to create app data dir!")?;
+    tokio::fs::write(
+        app_data.join(JOURNAL),
+        serde_json::to_string(partials).context("Failed to serialize partial outputs!")?,
+    )
+    .await
+    .context("Failed to write partial outputs!")
+}
+
+/// Notes that `partial` is about to be written to. A stale copy left over from an earlier
+/// attempt is removed, since ffmpeg won't overwrite it
+pub async fn begin(app_data: &Path, partial: &Path) -> Result<()> {
+    let _lock = JOURNAL_LOCK.lock().await;
+
+    match tokio::fs::remove_file(partial).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(e).context("Failed to remove stale partial output!")
+        }
+        _ => (),
+    }
+
+    let mut partials = read_journal(app_data).await?;
+    if !partials.iter().any(|p| p == partial) {
+        partials.push(partial.to_path_buf());
+    }
+    write_journal(app_data, &partials).await
+}
+
+/// Moves the finished, journaled partial of `output` into place, refusing to replace an
+/// existing file unless `overwrite` is set
+pub async fn commit(app_data: &Path, output: &Path, overwrite: bool) -> Result<()> {
+    let partial = path_for(output);
+
+    if !overwrite && tokio::fs::try_exists(output).await.unwrap_or(false) {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(anyhow::Error::msg(format!(
+            "The file at `{}` already exists!",
+            output.display()
+        )));
+    }
+
+    finish(output).await?;
+
+    forget(app_data, &partial).await
+}
+
+/// Drops `partial` from the journal, once it has been renamed or removed
+pub async fn forget(app_data: &Path, partial: &Path) -> Result<()> {
+    let _lock = JOURNAL_LOCK.lock().await;
+
+    let mut partials = read_journal(app_data).await?;
+    partials.retain(|p| p != partial);
+    write_journal(app_data, &partials).await
+}
+
+/// Removes every output left behind by an earlier session that didn't finish writing it. Must
+/// run before any downloads start
+pub async fn sweep(app_data: &Path) -> Result<()> {
+    let _lock = JOURNAL_LOCK.lock().await;
+
+    let partials = read_journal(app_data).await?;
+    let mut remaining = Vec::new();
+
+    for partial in partials {
+        match tokio::fs::remove_file(&partial).await {
+            Ok(()) => info!("Removed orphaned partial output `{}`", partial.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => {
+                warn!(
+                    "Failed to remove partial output `{}`: {e}",
+                    partial.display()
+                );
+                remaining.push(partial);
+            }
+        }
+    }
+
+    write_journal(app_data, &remaining).await
+}
@@ -0,0 +1,322 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::prelude::*;
+
+use chrono::{DateTime, Local, Timelike};
+use tauri::{AppHandle, Emitter, Manager};
+
+use super::{
+    auth::Token,
+    batches::{BatchKind, DownloadManager},
+    get_resolved_settings,
+    sync::{self, SyncReport},
+};
+
+/// How often the scheduler checks whether a sync is due
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Only this many runs are kept in the history
+const MAX_HISTORY: usize = 100;
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct SyncSchedule {
+    /// Sync every time the app is launched
+    #[serde(default)]
+    pub on_launch: bool,
+    /// Sync once a day, as soon as this hour (local time) has passed
+    #[serde(default)]
+    pub daily_at: Option<u32>,
+    /// Only start syncing between these hours (local time), as in [`in_window`]
+    #[serde(default)]
+    pub window: Option<(u32, u32)>,
+    /// Sync even when the connection is metered
+    #[serde(default)]
+    pub allow_metered: bool,
+}
+
+impl SyncSchedule {
+    /// Rejects hours and windows that are never reached, which would keep the schedule from ever
+    /// firing
+    pub fn validate(&self) -> Result<()> {
+        let hours = self.daily_at.into_iter().chain(
+            self.window
+                .into_iter()
+                .flat_map(|(start, end)| [start, end]),
+        );
+
+        for hour in hours {
+            if hour > 23 {
+                return Err(anyhow::Error::msg(format!(
+                    "{hour} is not an hour of the day! Sync hours go from 0 to 23."
+                )));
+            }
+        }
+
+        if let Some((start, end)) = self.window {
+            if start == end {
+                return Err(anyhow::Error::msg(format!(
+                    "The sync window from {start} to {end} never opens! It has to end at a different hour than it starts."
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Trigger {
+    Launch,
+    Daily,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncRun {
+    pub trigger: Trigger,
+    pub started_at: DateTime<Local>,
+    pub finished_at: DateTime<Local>,
+    pub report: Option<SyncReport>,
+    pub error: Option<String>,
+}
+
+pub async fn load_history(app_data: &Path) -> Result<Vec<SyncRun>> {
+    let path = app_data.join("sync_history.json");
+
+    if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+        return Ok(vec![]);
+    }
+
+    serde_json::from_slice(
+        &tokio::fs::read(path)
+            .await
+            .context("Failed to read sync_history.json!")?,
+    )
+    .context("Failed to parse sync_history.json!")
+}
+
+async fn push_history(app_data: &Path, run: SyncRun) -> Result<()> {
+    let mut history = load_history(app_data).await.unwrap_or_default();
+    history.push(run);
+
+    if history.len() > MAX_HISTORY {
+        history.drain(..history.len() - MAX_HISTORY);
+    }
+
+    tokio::fs::create_dir_all(app_data)
+        .await
+        .context("Failed to create app data dir!")?;
+
+    tokio::fs::write(
+        app_data.join("sync_history.json"),
+        serde_json::to_vec(&history).context("Failed to serialize sync history!")?,
+    )
+    .await
+    .context("Failed to write sync_history.json!")
+}
+
+/// Whether the hour is in the window, which wraps around midnight when it ends before it starts,
+/// so `(22, 6)` is 10pm to 6am
+pub fn in_window(hour: u32, (start, end): (u32, u32)) -> bool {
+    if start <= end {
+        (start..end).contains(&hour)
+    } else {
+        hour >= start || hour < end
+    }
+}
+
+/// Asks NetworkManager whether the connection is metered. Connections are assumed to be
+/// unmetered wherever this can't be found out
+#[cfg(target_os = "linux")]
+async fn is_metered() -> bool {
+    let output = tokio::task::spawn_blocking(|| {
+        std::process::Command::new("busctl")
+            .args([
+                "get-property",
+                "org.freedesktop.NetworkManager",
+                "/org/freedesktop/NetworkManager",
+                "org.freedesktop.NetworkManager",
+                "Metered",
+            ])
+            .output()
+    })
+    .await;
+
+    match output {
+        // 1 = metered, 3 = guessed to be metered
+        Ok(Ok(output)) => matches!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            "u 1" | "u 3"
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn is_metered() -> bool {
+    false
+}
+
+/// Whether the schedule allows a sync to start right now
+async fn allowed(schedule: &SyncSchedule) -> bool {
+    if let Some(window) = schedule.window {
+        if !in_window(Local::now().hour(), window) {
+            debug!("Outside of the sync window {window:?}");
+            return false;
+        }
+    }
+
+    if !schedule.allow_metered && is_metered().await {
+        debug!("Connection is metered");
+        return false;
+    }
+
+    true
+}
+
+#[instrument(skip(app, app_data))]
+async fn run(app: &AppHandle, app_data: &Path, trigger: Trigger) {
+    info!("Starting background sync");
+
+    let started_at = Local::now();
+    let token = Arc::clone(&app.state::<Arc<Token>>());
+    let batch = app
+        .state::<Arc<DownloadManager>>()
+        .start(app, BatchKind::BackgroundSync, None);
+
+    let progress_batch = batch.clone();
+    let result = sync::sync(
+        app,
+        app_data,
+        token,
+        batch.clone(),
+        false,
+        Arc::new(move |percent| progress_batch.set_progress(percent)),
+        &|errors| warn!("Background sync error: {}", errors.join(": ")),
+    )
+    .await
+    .inspect_err(|e| error!("Background sync failed: {e}"));
+
+    batch.finish(&result);
+    drop(batch);
+
+    let run = SyncRun {
+        trigger,
+        started_at,
+        finished_at: Local::now(),
+        error: result.as_ref().err().map(|e| e.to_string()),
+        report: result.ok(),
+    };
+
+    let _ = app.emit("background-sync", &run);
+
+    let _ = push_history(app_data, run)
+        .await
+        .inspect_err(|e| error!("Failed to save sync history: {e}"));
+
+    info!("Background sync finished");
+}
+
+/// Runs syncs on launch and daily, as set up in the user's settings. Runs for as long as the app does
+#[instrument(skip_all)]
+pub async fn run_scheduler(app: AppHandle) {
+    let app_data: PathBuf = match app.path().app_data_dir() {
+        Ok(app_data) => app_data,
+        Err(e) => {
+            error!("Failed to read app data dir, background syncs are disabled: {e}");
+            return;
+        }
+    };
+
+    let schedule = get_resolved_settings(&app).await.sync_schedule;
+    if schedule.on_launch && allowed(&schedule).await {
+        run(&app, &app_data, Trigger::Launch).await;
+    }
+
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        // Settings are read every time, so changes apply without restarting the app
+        let schedule = get_resolved_settings(&app).await.sync_schedule;
+        let Some(daily_at) = schedule.daily_at else {
+            continue;
+        };
+
+        let now = Local::now();
+        if now.hour() < daily_at {
+            continue;
+        }
+
+        let ran_today = load_history(&app_data)
+            .await
+            .unwrap_or_default()
+            .iter()
+            .any(|run| {
+                run.trigger == Trigger::Daily && run.started_at.date_naive() == now.date_naive()
+            });
+
+        if !ran_today && allowed(&schedule).await {
+            run(&app, &app_data, Trigger::Daily).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_within_a_day() {
+        assert!(in_window(9, (9, 17)));
+        assert!(in_window(16, (9, 17)));
+        assert!(!in_window(17, (9, 17)));
+        assert!(!in_window(8, (9, 17)));
+    }
+
+    #[test]
+    fn window_wrapping_around_midnight() {
+        assert!(in_window(22, (22, 6)));
+        assert!(in_window(0, (22, 6)));
+        assert!(in_window(5, (22, 6)));
+        assert!(!in_window(6, (22, 6)));
+        assert!(!in_window(12, (22, 6)));
+    }
+
+    #[test]
+    fn empty_window_never_matches() {
+        assert!(!in_window(3, (3, 3)));
+    }
+
+    #[test]
+    fn rejects_hours_past_the_end_of_the_day() {
+        let schedule = |daily_at, window| SyncSchedule {
+            daily_at,
+            window,
+            ..Default::default()
+        };
+
+        assert!(schedule(Some(23), Some((0, 23))).validate().is_ok());
+        assert!(schedule(None, None).validate().is_ok());
+        assert!(schedule(Some(24), None).validate().is_err());
+        assert!(schedule(None, Some((22, 30))).validate().is_err());
+    }
+
+    #[test]
+    fn rejects_empty_windows() {
+        let schedule = |window| SyncSchedule {
+            daily_at: Some(3),
+            window,
+            ..Default::default()
+        };
+
+        assert!(schedule(Some((22, 6))).validate().is_ok());
+        assert!(schedule(Some((3, 3))).validate().is_err());
+        assert!(schedule(Some((0, 0))).validate().is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use std::{path::Path, sync::Arc};
+
+use crate::prelude::*;
+
+use tauri::AppHandle;
+
+use super::{
+    auth::Token,
+    batches::BatchHandle,
+    download_videos, get_resolved_settings,
+    lex::{self, Lecture, Subject},
+    output_location, Settings, Video,
+};
+
+/// A lecture whose new videos are downloaded into `folder` whenever a sync runs
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Subscription {
+    pub subject: Subject,
+    pub lecture: Lecture,
+    pub folder: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MissingVideo {
+    pub ttid: i32,
+    pub number: i32,
+    pub topic: String,
+    pub location: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionReport {
+    pub subject: String,
+    pub section: String,
+    pub folder: String,
+    /// Videos that were not in the folder when the sync started
+    pub missing: Vec<MissingVideo>,
+    /// Ttids of the videos that were downloaded, which is empty on a dry run
+    pub downloaded: Vec<i32>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncReport {
+    pub dry_run: bool,
+    pub subscriptions: Vec<SubscriptionReport>,
+}
+
+pub async fn load_subscriptions(app_data: &Path) -> Result<Vec<Subscription>> {
+    let path = app_data.join("subscriptions.json");
+
+    if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+        return Ok(vec![]);
+    }
+
+    serde_json::from_slice(
+        &tokio::fs::read(path)
+            .await
+            .context("Failed to read subscriptions.json!")?,
+    )
+    .context("Failed to parse subscriptions.json!")
+}
+
+pub async fn save_subscriptions(app_data: &Path, subscriptions: &[Subscription]) -> Result<()> {
+    tokio::fs::create_dir_all(app_data)
+        .await
+        .context("Failed to create app data dir!")?;
+
+    tokio::fs::write(
+        app_data.join("subscriptions.json"),
+        serde_json::to_vec(subscriptions).context("Failed to serialize subscriptions!")?,
+    )
+    .await
+    .context("Failed to write subscriptions.json!")
+}
+
+/// Videos of the subscribed lecture that are not in its folder yet
+async fn find_missing(
+    token: &Token,
+    settings: &Settings,
+    subscription: &Subscription,
+) -> Result<Vec<Video>> {
+    // Resolves the lecture again, so that one dropped from Lex fails clearly instead of looking empty
+    let lecture = lex::lecture(
+        token,
+        &subscription.subject.id.id,
+        &subscription.lecture.id.id,
+    )
+    .await?;
+    let videos = lex::subject_videos(token, &subscription.subject, &lecture).await?;
+
+    let mut missing = vec![];
+    for video in videos {
+        let location = output_location(settings, &video, &subscription.folder);
+        if !tokio::fs::try_exists(&location).await.unwrap_or(false) {
+            missing.push(video);
+        }
+    }
+
+    Ok(missing)
+}
+
+/// Downloads the videos of every subscription that are missing from their folders. On a dry run,
+/// only reports what would be downloaded
+#[instrument(skip_all, fields(dry_run))]
+pub async fn sync(
+    app: &AppHandle,
+    app_data: &Path,
+    token: Arc<Token>,
+    batch: BatchHandle,
+    dry_run: bool,
+    on_progress: Arc<dyn Fn(f32) + Send + Sync>,
+    on_error: &(dyn Fn(Vec<String>) + Sync),
+) -> Result<SyncReport> {
+    let settings = get_resolved_settings(app).await;
+    let subscriptions = load_subscriptions(app_data).await?;
+
+    info!("Syncing {} subscriptions", subscriptions.len());
+
+    let mut reports = Vec::with_capacity(subscriptions.len());
+
+    for (i, subscription) in subscriptions.iter().enumerate() {
+        let mut report = SubscriptionReport {
+            subject: subscription.subject.name.clone(),
+            section: subscription.lecture.section.clone(),
+            folder: subscription.folder.clone(),
+            missing: vec![],
+            downloaded: vec![],
+            error: None,
+        };
+
+        let missing = match find_missing(&token, &settings, subscription).await {
+            Ok(missing) => missing,
+            Err(err) => {
+                error!("Failed to find missing videos of {}: {err}", report.subject);
+                on_error(vec![
+                    format!("Failed to sync {}", report.subject),
+                    err.to_string(),
+                ]);
+                report.error = Some(err.to_string());
+                reports.push(report);
+                continue;
+            }
+        };
+
+        info!("{} is missing {} videos", report.subject, missing.len());
+
+        report.missing = missing
+            .iter()
+            .map(|video| MissingVideo {
+                ttid: video.ttid,
+                number: video.number,
+                topic: video.topic.clone(),
+                location: output_location(&settings, video, &subscription.folder)
+                    .to_string_lossy()
+                    .to_string(),
+            })
+            .collect();
+
+        if !dry_run && !missing.is_empty() {
+            // Each subscription takes up an equal share of the overall progress
+            let on_progress = on_progress.clone();
+            let count = subscriptions.len() as f32;
+
+            match download_videos(
+                app.clone(),
+                token.clone(),
+                subscription.folder.clone(),
+                missing,
+                batch.clone(),
+                move |percent| on_progress((i as f32 * 100.0 + percent) / count),
+                on_error,
+            )
+            .await
+            {
+                Ok(downloaded) => report.downloaded = downloaded,
+                Err(err) => {
+                    // The rest are still synced, with the error kept on this subscription's entry
+                    error!(
+                        "Failed to download missing videos of {}: {err}",
+                        report.subject
+                    );
+                    on_error(vec![
+                        format!("Failed to sync {}", report.subject),
+                        err.clone(),
+                    ]);
+                    report.error = Some(err);
+                }
+            }
+        }
+
+        reports.push(report);
+
+        if batch.cancellation_token().is_cancelled() {
+            info!("Sync cancelled");
+            break;
+        }
+    }
+
+    on_progress(100.0);
+
+    Ok(SyncReport {
+        dry_run,
+        subscriptions: reports,
+    })
+}
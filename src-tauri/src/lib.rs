@@ -1,13 +1,34 @@
-use tokio::sync::Mutex;
-use tokio_util::sync::CancellationToken;
+use std::sync::Arc;
+
+use commands::{auth::Token, batches::DownloadManager};
+use tauri::{Emitter, Manager};
 
 mod commands;
 pub mod prelude;
+pub mod redact;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
-        .manage(Mutex::new(CancellationToken::new()))
+        .manage(Arc::new(DownloadManager::new()))
+        .setup(|app| {
+            let handle = app.handle().clone();
+            // The frontend listens for this, and answers with `set_token`
+            app.manage(Arc::new(Token::new(move || {
+                let _ = handle.emit("token-expired", ());
+            })));
+            // Finished before the frontend can start any downloads, whose outputs it would remove
+            tauri::async_runtime::block_on(commands::sweep_partial_outputs(app.handle()));
+            let handle = app.handle().clone();
+            // Launch syncs may start downloading straight away, so the client and credentials
+            // have to come first
+            tauri::async_runtime::spawn(async move {
+                commands::configure_http(handle.clone()).await;
+                commands::restore_credentials(handle.clone()).await;
+                commands::schedule::run_scheduler(handle).await;
+            });
+            Ok(())
+        })
         .plugin(tauri_plugin_http::init())
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_opener::init())
@@ -16,11 +37,37 @@ pub fn run() {
         .invoke_handler(tauri::generate_handler![
             commands::download,
             commands::cancel_download,
+            commands::list_batches,
+            commands::bump_queued,
+            commands::defer_queued,
+            commands::reorder_queue,
             commands::clear_cache,
             commands::get_cache_size,
             commands::save_settings,
             commands::load_settings,
             commands::log_error,
+            commands::set_token,
+            commands::store_credentials,
+            commands::search_subjects,
+            commands::list_sessions,
+            commands::list_lectures,
+            commands::list_subscriptions,
+            commands::subscribe,
+            commands::unsubscribe,
+            commands::sync,
+            commands::get_sync_history,
+            commands::list_library,
+            commands::search_library,
+            commands::get_downloaded,
+            commands::verify_library,
+            commands::relink_library_entry,
+            commands::redownload_library_entries,
+            commands::export_library,
+            commands::check_disk_space,
+            commands::move_cache,
+            commands::list_cache,
+            commands::remove_cache_entry,
+            commands::remux_bundle,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
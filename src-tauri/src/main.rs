@@ -3,7 +3,7 @@
 use tracing::info;
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 
-use multipartus_downloader_lib::run;
+use multipartus_downloader_lib::{redact::Redacted, run};
 
 fn main() {
     // outfile
@@ -20,7 +20,7 @@ fn main() {
     let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
     tracing_subscriber::fmt()
         .with_ansi(false)
-        .with_writer(non_blocking)
+        .with_writer(Redacted(non_blocking))
         .init();
 
     info!("Starting multipartus-downloader");
@@ -0,0 +1,69 @@
+import { invoke } from "@tauri-apps/api/core";
+import { useState } from "react";
+import { toast } from "sonner";
+import { Button } from "./ui/button";
+
+type CacheEntry = {
+	ttid: number;
+	size: number;
+	chunks: number;
+	totalChunks: number | null;
+	lastAccess: string;
+};
+
+const MiB = (bytes: number) => `${(bytes / 1024 ** 2).toFixed(1)} MiB`;
+
+export const CacheList = ({ onChange }: { onChange: () => void }) => {
+	const [entries, setEntries] = useState<CacheEntry[] | null>(null);
+
+	async function refresh() {
+		try {
+			setEntries(await invoke("list_cache"));
+		} catch (e) {
+			toast.error("Failed to list the cache!");
+			console.error("Failed to list cache!", e);
+		}
+	}
+
+	async function remove(ttid: number) {
+		try {
+			await invoke("remove_cache_entry", { ttid });
+			setEntries((prev) => prev?.filter((entry) => entry.ttid !== ttid) ?? null);
+			onChange();
+		} catch (e) {
+			toast.error(`${e}`);
+		}
+	}
+
+	if (entries == null) {
+		return (
+			<Button variant="secondary" onClick={refresh}>
+				Show Cached Lectures
+			</Button>
+		);
+	}
+
+	return (
+		<div className="flex flex-col gap-2">
+			{entries.length === 0 && <p className="text-xs">Nothing is cached</p>}
+			{entries.map((entry) => (
+				<div
+					key={entry.ttid}
+					className="flex items-center justify-between gap-2 text-xs border rounded-sm p-2"
+				>
+					<div>
+						<b>Lecture {entry.ttid}</b> ({MiB(entry.size)})
+						<br />
+						{entry.totalChunks
+							? `${Math.min(100, (entry.chunks / entry.totalChunks) * 100).toFixed(0)}% downloaded`
+							: `${entry.chunks} chunks`}
+						, last used {new Date(entry.lastAccess).toLocaleString("en-IN")}
+					</div>
+					<Button size="sm" variant="destructive" onClick={() => remove(entry.ttid)}>
+						Delete
+					</Button>
+				</div>
+			))}
+		</div>
+	);
+};
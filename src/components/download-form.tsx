@@ -1,11 +1,14 @@
-import { subjectAtom, videosAtom } from "@/lib/atoms";
+import { subjectAtom, subjectDetailsAtom, videosAtom } from "@/lib/atoms";
+import { markDownloaded } from "@/lib/library";
 import { logtoClient } from "@/lib/logto";
 import { Channel, invoke } from "@tauri-apps/api/core";
-import { open as openDialog } from "@tauri-apps/plugin-dialog";
-import { useAtomValue } from "jotai";
-import { BirdIcon, DownloadIcon } from "lucide-react";
-import { useMemo, useState } from "react";
-import { LectureSelector } from "./lecture-selector";
+import { listen } from "@tauri-apps/api/event";
+import { confirm, open as openDialog } from "@tauri-apps/plugin-dialog";
+import { useAtom, useAtomValue } from "jotai";
+import { BellIcon, BirdIcon, DownloadIcon, ListVideoIcon } from "lucide-react";
+import { useEffect, useMemo, useRef, useState } from "react";
+import { DownloadQueue } from "./download-queue";
+import { LectureSelector, lectureDetailsAtom } from "./lecture-selector";
 import { SubjectSelector } from "./subject-selector";
 import { Button } from "./ui/button";
 import {
@@ -16,8 +19,10 @@ import {
 	DialogTitle,
 } from "./ui/dialog";
 import { Progress } from "./ui/progress";
+import Tooltip from "./ui/tooltip";
 import { MasterSelects, VideoSelector } from "./video-selector";
 import { LoadingDots } from "./ui/load-dots";
+import { toast } from "sonner";
 
 type DownloadProgressEvent = {
 	percent: number;
@@ -27,8 +32,60 @@ type DownloadErrorEvent = {
 	errors: [string, string];
 };
 
+type DiskSpaceReport = {
+	tempRequired: number;
+	tempAvailable: number;
+	destinationRequired: number;
+	destinationAvailable: number;
+	sameDisk: boolean;
+	sufficient: boolean;
+	unknown: number[];
+};
+
+type BatchStartedEvent = {
+	id: number;
+};
+
+type DownloadCancelledEvent = {
+	batch: number;
+	ttid: number;
+	number: number;
+};
+
+type DiskSpaceEvent = {
+	low: boolean;
+	available: number;
+};
+
+const GiB = (bytes: number) => `${(bytes / 1024 ** 3).toFixed(1)} GiB`;
+
+// Checks there is room for the downloads, asking whether to go ahead if there isn't
+async function preflight(
+	token: string | null,
+	folder: string,
+	videos: Multipartus.Video[],
+): Promise<boolean> {
+	let report: DiskSpaceReport;
+	try {
+		report = await invoke("check_disk_space", { token, folder, videos });
+	} catch (e) {
+		console.error("Failed to check disk space", e);
+		return true;
+	}
+	if (report.sufficient) return true;
+
+	const required = report.sameDisk
+		? `${GiB(report.tempRequired + report.destinationRequired)} is needed, but only ${GiB(report.tempAvailable)} is free.`
+		: `${GiB(report.tempRequired)} is needed in the cache (${GiB(report.tempAvailable)} free) and ${GiB(report.destinationRequired)} in the download folder (${GiB(report.destinationAvailable)} free).`;
+
+	return await confirm(
+		`There might not be enough disk space for these lectures. ${required}\n\nDownloads pause when the disk is nearly full. Download anyway?`,
+		{ title: "Low Disk Space", kind: "warning" },
+	);
+}
+
 const DownloadButton = () => {
-	const videos = useAtomValue(videosAtom);
+	const [videos, setVideos] = useAtom(videosAtom);
 	const selectedVideos = useMemo(
 		() => videos.filter((v) => v.selected),
 		[videos],
@@ -37,6 +94,16 @@ const DownloadButton = () => {
 	const [progressPercentage, setProgressPercentage] = useState(0);
 	const [errors, setErrors] = useState<[string, string][]>([]);
 	const [complete, setComplete] = useState(false);
+	const [cancelled, setCancelled] = useState<number[]>([]);
+	// Other downloads may be running alongside this one, like background syncs
+	const batch = useRef<number | null>(null);
+	const [batchId, setBatchId] = useState<number | null>(null);
+
+	const onStart = new Channel<BatchStartedEvent>();
+	onStart.onmessage = ({ id }) => {
+		batch.current = id;
+		setBatchId(id);
+	};
 
 	const onProgress = new Channel<DownloadProgressEvent>();
 	onProgress.onmessage = (message) => setProgressPercentage(message?.percent);
@@ -45,6 +112,29 @@ const DownloadButton = () => {
 	onError.onmessage = (message) =>
 		setErrors((prevErrors) => [...prevErrors, message.errors]);
 
+	useEffect(() => {
+		const unlisten = listen<DiskSpaceEvent>("disk-space", ({ payload }) => {
+			if (payload.low) {
+				toast.warning(
+					`Downloads paused, only ${GiB(payload.available)} of disk space is left. Free up some space to continue.`,
+				);
+			} else {
+				toast.info("Downloads resumed");
+			}
+		});
+		const unlistenCancelled = listen<DownloadCancelledEvent>(
+			"download-cancelled",
+			({ payload }) => {
+				if (payload.batch !== batch.current) return;
+				setCancelled((prev) => [...prev, payload.number]);
+			},
+		);
+		return () => {
+			unlisten.then((f) => f());
+			unlistenCancelled.then((f) => f());
+		};
+	}, []);
+
 	function openable(openState: boolean) {
 		if (complete) {
 			setOpen(openState);
@@ -54,7 +144,10 @@ const DownloadButton = () => {
 	async function handleClick() {
 		setProgressPercentage(0);
 		setErrors([]);
+		setCancelled([]);
 		setComplete(false);
+		batch.current = null;
+		setBatchId(null);
 
 		const baseFolder = await openDialog({
 			directory: true,
@@ -63,6 +156,7 @@ const DownloadButton = () => {
 		if (!baseFolder) return;
 
 		const token = await logtoClient.getIdToken();
+		if (!(await preflight(token, baseFolder, selectedVideos))) return;
 		setOpen(true);
 
 		// Use base folder instead of adding temp, since the temp file is chosen to be the default temp
@@ -72,6 +166,7 @@ const DownloadButton = () => {
 				token,
 				folder: baseFolder,
 				videos: selectedVideos,
+				onStart,
 				onProgress,
 				onError,
 			});	
@@ -80,6 +175,7 @@ const DownloadButton = () => {
 			setErrors(prevErrors => [...prevErrors, ["An unexpected error occured while downloading", `${error}`]])
 		}
 		setComplete(true);
+		setVideos(await markDownloaded(videos));
 	}
 
 	return (
@@ -91,7 +187,9 @@ const DownloadButton = () => {
 			<DialogContent>
 				<DialogTitle>
 					{complete
-						? errors.length > 0
+						? cancelled.length > 0
+							? "Lecture Downloads Cancelled"
+							: errors.length > 0
 							? "Some Lecture Downloads Failed!"
 							: "Lecture Downloads Complete!"
 						: <>Downloading Your Lectures<LoadingDots /></>}
@@ -101,6 +199,14 @@ const DownloadButton = () => {
 					{progressPercentage.toFixed(1)}% Complete
 				</DialogDescription>
 				<Progress value={progressPercentage} />
+				{cancelled.length > 0 && (
+					<p className="text-sm text-muted-foreground">
+						Cancelled Lecture{cancelled.length > 1 && "s"}{" "}
+						{[...cancelled].sort((a, b) => a - b).join(", ")}, their partial files
+						have been removed.
+					</p>
+				)}
+				{!complete && batchId !== null && <DownloadQueue batch={batchId} />}
 				{errors.length > 0 && (
 					<b>
 						Errors:
@@ -123,8 +229,8 @@ const DownloadButton = () => {
 						Done
 					</Button>
 					<Button
-						onClick={() => invoke("cancel_download")}
-						disabled={complete}
+						onClick={() => invoke("cancel_download", { id: batchId })}
+						disabled={complete || batchId === null}
 						variant="destructive"
 					>
 						Cancel
@@ -135,6 +241,70 @@ const DownloadButton = () => {
 	);
 };
 
+const ExportButton = () => {
+	const videos = useAtomValue(videosAtom);
+	const subjectName = videos.find((video) => video.downloaded)?.subjectName;
+
+	async function handleClick() {
+		const folder = await openDialog({
+			title: "Export playlists and index to",
+			directory: true,
+			multiple: false,
+		});
+		if (!folder) return;
+
+		try {
+			await invoke("export_library", { subject: subjectName, folder });
+			toast.success(`Exported ${subjectName} playlists and index!`);
+		} catch (e) {
+			toast.error(`${e}`);
+		}
+	}
+
+	return (
+		<Tooltip content="Export playlists and an index page of downloaded lectures">
+			<Button variant="secondary" disabled={!subjectName} onClick={handleClick}>
+				<ListVideoIcon />
+			</Button>
+		</Tooltip>
+	);
+};
+
+const SubscribeButton = () => {
+	const subject = useAtomValue(subjectDetailsAtom);
+	const lecture = useAtomValue(lectureDetailsAtom);
+
+	async function handleClick() {
+		const folder = await openDialog({
+			title: "Download new lectures of this section to",
+			directory: true,
+			multiple: false,
+		});
+		if (!folder) return;
+
+		try {
+			await invoke("subscribe", { subject, lecture, folder });
+			toast.success(
+				`Subscribed to ${subject?.name} ${lecture?.section}, new lectures are downloaded when syncing`,
+			);
+		} catch (e) {
+			toast.error(`${e}`);
+		}
+	}
+
+	return (
+		<Tooltip content="Subscribe to this section, so that its new lectures are downloaded when syncing">
+			<Button
+				variant="secondary"
+				disabled={!subject || !lecture}
+				onClick={handleClick}
+			>
+				<BellIcon />
+			</Button>
+		</Tooltip>
+	);
+};
+
 export const DownloadForm = () => {
 	const subject = useAtomValue(subjectAtom);
 
@@ -147,6 +317,8 @@ export const DownloadForm = () => {
 						<LectureSelector />
 						<MasterSelects />
 						<DownloadButton />
+						<ExportButton />
+						<SubscribeButton />
 					</div>
 					<VideoSelector />
 				</div>
@@ -0,0 +1,117 @@
+import { invoke } from "@tauri-apps/api/core";
+import {
+	ArrowDownToLine,
+	ArrowUpToLine,
+	ChevronDown,
+	ChevronUp,
+} from "lucide-react";
+import { useEffect, useState } from "react";
+import { toast } from "sonner";
+import { Button } from "./ui/button";
+import Tooltip from "./ui/tooltip";
+
+type QueuedLecture = {
+	ttid: number;
+	number: number;
+	topic: string;
+	priority: number;
+};
+
+type BatchInfo = {
+	id: number;
+	queue: QueuedLecture[];
+};
+
+// Lectures waiting in a running batch, which can be reordered before they start
+export const DownloadQueue = ({ batch }: { batch: number }) => {
+	const [queue, setQueue] = useState<QueuedLecture[]>([]);
+
+	async function refresh() {
+		try {
+			const batches: BatchInfo[] = await invoke("list_batches");
+			setQueue(batches.find(({ id }) => id === batch)?.queue ?? []);
+		} catch (e) {
+			console.error("Failed to list batches!", e);
+		}
+	}
+
+	useEffect(() => {
+		refresh();
+		const interval = setInterval(refresh, 1000);
+		return () => clearInterval(interval);
+	}, [batch]);
+
+	async function run(command: string, args: Record<string, unknown>) {
+		try {
+			await invoke(command, { batch, ...args });
+			await refresh();
+		} catch (e) {
+			// The lecture may have started in the meantime
+			toast.error(`${e}`);
+			await refresh();
+		}
+	}
+
+	// Swaps a lecture with its neighbour, by sending the whole new order
+	function move(from: number, to: number) {
+		const ttids = queue.map(({ ttid }) => ttid);
+		[ttids[from], ttids[to]] = [ttids[to], ttids[from]];
+		run("reorder_queue", { ttids });
+	}
+
+	if (queue.length === 0) return null;
+
+	return (
+		<div className="flex flex-col gap-1 max-h-40 overflow-auto">
+			<b className="text-sm">Up Next</b>
+			{queue.map((lecture, i) => (
+				<div
+					key={lecture.ttid}
+					className="flex items-center justify-between gap-2 text-xs border rounded-sm px-2 py-1"
+				>
+					<span className="truncate">
+						<b>{lecture.number}</b> {lecture.topic}
+					</span>
+					<div className="flex">
+						<Tooltip content="Download first">
+							<Button
+								size="icon"
+								variant="ghost"
+								disabled={i === 0}
+								onClick={() => run("bump_queued", { ttid: lecture.ttid })}
+							>
+								<ArrowUpToLine />
+							</Button>
+						</Tooltip>
+						<Button
+							size="icon"
+							variant="ghost"
+							disabled={i === 0}
+							onClick={() => move(i, i - 1)}
+						>
+							<ChevronUp />
+						</Button>
+						<Button
+							size="icon"
+							variant="ghost"
+							disabled={i === queue.length - 1}
+							onClick={() => move(i, i + 1)}
+						>
+							<ChevronDown />
+						</Button>
+						<Tooltip content="Download last">
+							<Button
+								size="icon"
+								variant="ghost"
+								disabled={i === queue.length - 1}
+								onClick={() => run("defer_queued", { ttid: lecture.ttid })}
+							>
+								<ArrowDownToLine />
+							</Button>
+						</Tooltip>
+					</div>
+				</div>
+			))}
+		</div>
+	);
+};
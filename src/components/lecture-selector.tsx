@@ -1,5 +1,6 @@
 import { lectureAtom, subjectAtom } from "@/lib/atoms";
-import { fetchLex } from "@/lib/lex";
+import { logtoClient } from "@/lib/logto";
+import { invoke } from "@tauri-apps/api/core";
 import { atom, useAtom, useAtomValue } from "jotai";
 import { loadable } from "jotai/utils";
 import { useEffect } from "react";
@@ -24,9 +25,9 @@ const getSessionLabel = (
 	return "unknown session";
 };
 
-const sessionsAtom = atom(async (_, { signal }) => {
-	const sessions = await fetchLex<Multipartus.Sessions>("session", { signal });
-	return sessions;
+const sessionsAtom = atom(async () => {
+	const token = await logtoClient.getIdToken();
+	return invoke<Multipartus.Sessions>("list_sessions", { token });
 });
 
 const lecturesAtom = loadable(
@@ -38,11 +39,13 @@ const lecturesAtom = loadable(
 		}
 
 		const sessions = await get(sessionsAtom);
-		const lectures = await fetchLex<Multipartus.Lecture[]>(
-			`subject/${subject[0].replaceAll("/", ",")}/${subject[1]}/lectures`,
-		);
+		const lectures = await invoke<Multipartus.Lecture[]>("list_lectures", {
+			token: await logtoClient.getIdToken(),
+			subject,
+		});
 
 		return lectures.map((lecture) => ({
+			lecture,
 			id: lecture.id.ID,
 			value: lecture.id.ID.join(";"),
 			label: [
@@ -54,6 +57,15 @@ const lecturesAtom = loadable(
 	}),
 );
 
+// the selected lecture section as Lex lists it, for subscribing to it
+export const lectureDetailsAtom = atom((get) => {
+	const lectures = get(lecturesAtom);
+	const selected = get(lectureAtom);
+	if (lectures.state !== "hasData" || !selected) return undefined;
+	return lectures.data.find(({ id }) => id.join(";") === selected.join(";"))
+		?.lecture;
+});
+
 export function LectureSelector() {
 	const [selectedLecture, selectLecture] = useAtom(lectureAtom);
 	const lectures = useAtomValue(lecturesAtom);
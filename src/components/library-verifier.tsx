@@ -0,0 +1,148 @@
+import { logtoClient } from "@/lib/logto";
+import { Channel, invoke } from "@tauri-apps/api/core";
+import { open as openDialog } from "@tauri-apps/plugin-dialog";
+import { useState } from "react";
+import { toast } from "sonner";
+import { Button } from "./ui/button";
+
+type EntryStatus =
+	| "Ok"
+	| "Missing"
+	| "SizeMismatch"
+	| "ChecksumMismatch"
+	| "DurationMismatch"
+	| "Unreadable"
+	| "Unverifiable";
+
+type VerifiedEntry = {
+	entry: {
+		ttid: number;
+		subject: string;
+		number: number;
+		topic: string;
+		path: string;
+	};
+	status: EntryStatus;
+	actualDuration: number | null;
+	error: string | null;
+};
+
+const describe: Record<EntryStatus, string> = {
+	Ok: "Ok",
+	Missing: "File is missing",
+	SizeMismatch: "File size changed",
+	ChecksumMismatch: "File is corrupted",
+	DurationMismatch: "Video is incomplete",
+	Unreadable: "File couldn't be read",
+	Unverifiable: "Couldn't check whether the video is complete",
+};
+
+export const LibraryVerifier = () => {
+	const [failed, setFailed] = useState<VerifiedEntry[]>([]);
+	const [verifying, setVerifying] = useState(false);
+
+	async function verify() {
+		setVerifying(true);
+		try {
+			const verified: VerifiedEntry[] = await invoke("verify_library");
+			const broken = verified.filter(({ status }) => status !== "Ok");
+			setFailed(broken);
+			if (broken.length === 0) {
+				toast.success(`All ${verified.length} downloaded lectures are fine!`);
+			}
+		} catch (e) {
+			toast.error("Failed to verify the library!");
+			console.error("Failed to verify library!", e);
+		}
+		setVerifying(false);
+	}
+
+	async function relink(path: string) {
+		const newPath = await openDialog({
+			multiple: false,
+			filters: [{ name: "Video", extensions: ["mp4"] }],
+		});
+		if (!newPath) return;
+
+		try {
+			await invoke("relink_library_entry", { path, newPath });
+			setFailed((prev) => prev.filter(({ entry }) => entry.path !== path));
+			toast.success("Relinked lecture!");
+		} catch (e) {
+			toast.error(`${e}`);
+		}
+	}
+
+	async function redownload(paths: string[]) {
+		const token = await logtoClient.getIdToken();
+		const onProgress = new Channel();
+		const onError = new Channel<{ errors: [string, string] }>();
+		onError.onmessage = ({ errors }) =>
+			toast.error(`Lecture ${errors[0]} failed: ${errors[1]}`);
+
+		toast.info("Downloading lectures again...");
+		try {
+			await invoke("redownload_library_entries", {
+				token,
+				paths,
+				onStart: new Channel(),
+				onProgress,
+				onError,
+			});
+			setFailed((prev) =>
+				prev.filter(({ entry }) => !paths.includes(entry.path)),
+			);
+			toast.success("Downloaded lectures again!");
+		} catch (e) {
+			toast.error(`${e}`);
+		}
+	}
+
+	return (
+		<div className="flex flex-col gap-2">
+			<div className="flex gap-4">
+				<Button variant="secondary" onClick={verify} disabled={verifying}>
+					{verifying ? "Verifying..." : "Verify Library"}
+				</Button>
+				<p className="text-xs place-self-center">
+					Checks that downloaded lectures are still where they were saved,
+					and are complete
+				</p>
+			</div>
+			{failed.map(({ entry, status, error }) => (
+				<div
+					key={entry.path}
+					className="flex items-center justify-between gap-2 text-xs border rounded-sm p-2"
+				>
+					<div>
+						<b>
+							{entry.subject} #{entry.number}
+						</b>{" "}
+						{entry.topic}
+						<br />
+						{describe[status]}
+						{error && `: ${error}`}
+					</div>
+					<div className="flex gap-2">
+						{status !== "DurationMismatch" && status !== "Unverifiable" && (
+							<Button size="sm" variant="secondary" onClick={() => relink(entry.path)}>
+								Re-link
+							</Button>
+						)}
+						<Button size="sm" onClick={() => redownload([entry.path])}>
+							Re-download
+						</Button>
+					</div>
+				</div>
+			))}
+			{failed.length > 1 && (
+				<Button
+					variant="secondary"
+					onClick={() => redownload(failed.map(({ entry }) => entry.path))}
+				>
+					Re-download All
+				</Button>
+			)}
+		</div>
+	);
+};
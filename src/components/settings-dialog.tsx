@@ -1,7 +1,12 @@
 import { invoke } from "@tauri-apps/api/core";
+import { open as openDialog, save as saveDialog } from "@tauri-apps/plugin-dialog";
 import { Settings } from "lucide-react";
 import { useState } from "react";
+import { CacheList } from "./cache-list";
+import { LibraryVerifier } from "./library-verifier";
+import { Subscriptions } from "./subscriptions";
 import { Button } from "./ui/button";
+import { Checkbox } from "./ui/checkbox";
 import { Dialog, DialogPortal } from "./ui/dialog";
 import { DialogContent, DialogTitle } from "./ui/dialog";
 import {
@@ -19,28 +24,85 @@ enum Resolution {
 	LowRes = "LowRes",
 }
 
+enum CredentialStore {
+	None = "None",
+	Keyring = "Keyring",
+	EncryptedFile = "EncryptedFile",
+}
+
 type AppSettings = {
 	resolution: Resolution;
 	base: string | null;
 	format: string | null;
+	credential_store: CredentialStore;
+	min_free_space: number;
+	cache_dir: string | null;
+	max_cache_size: number | null;
+	archive: boolean;
+	offline: boolean;
+	max_concurrent_lectures: number | null;
+	bandwidth?: BandwidthLimit;
+	http?: HttpConfig;
+	sync_schedule?: SyncSchedule;
+};
+
+type SyncSchedule = {
+	on_launch: boolean;
+	daily_at: number | null;
+	window: [number, number] | null;
+	allow_metered: boolean;
+};
+
+type HttpConfig = {
+	proxy: string | null;
+	root_certificates: string[];
+	connect_timeout: number | null;
+	read_timeout: number | null;
+	user_agent: string | null;
+};
+
+type ScheduledLimit = { window: [number, number]; limit: number | null };
+
+type BandwidthLimit = {
+	limit: number | null;
+	schedule: ScheduledLimit[];
+	requests_per_sec: number | null;
 };
 
 // Select remote automatically
 const AUTO = "Auto";
 
+function humanBytes(bytes: number) {
+	const units = ["B", "KiB", "MiB", "GiB", "TiB"];
+	let unit = 0;
+	while (bytes >= 1024 && unit < units.length - 1) {
+		bytes /= 1024;
+		unit++;
+	}
+	return `${bytes.toFixed(1)} ${units[unit]}`;
+}
+
 export const SettingsDialog = () => {
 	const [settings, setSettings] = useState<AppSettings>({
 		resolution: Resolution.HighRes,
 		base: null,
 		format: null,
+		credential_store: CredentialStore.None,
+		min_free_space: 1024,
+		cache_dir: null,
+		max_cache_size: null,
+		archive: false,
+		offline: false,
+		max_concurrent_lectures: null,
 	});
 
 	const [open, setOpen] = useState(false);
-	const [cacheSize, setCacheSize] = useState("0.0KiB");
+	const [cacheSize, setCacheSize] = useState("0.0 KiB");
 
 	async function computeCache() {
 		try {
-			setCacheSize(await invoke("get_cache_size"));
+			const { bytes }: { bytes: number; lectures: number } = await invoke("get_cache_size");
+			setCacheSize(humanBytes(bytes));
 		} catch (e) {
 			console.error("Failed computing size!", e);
 			setCacheSize("Unknown");
@@ -65,12 +127,56 @@ export const SettingsDialog = () => {
 		await computeCache();
 	}
 
+	async function moveCache(cacheDir: string | null) {
+		try {
+			const moved: number = await invoke("move_cache", { cacheDir });
+			setSettings((prev) => ({ ...prev, cache_dir: cacheDir }));
+			toast.success(`Moved the cache (${moved} lectures)`);
+		} catch (e) {
+			toast.error(`Failed to move the cache: ${e}`);
+			console.error("Failed to move cache!", e);
+		}
+		await computeCache();
+	}
+
+	async function pickCacheDir() {
+		const cacheDir = await openDialog({
+			title: "Select a folder for the cache",
+			directory: true,
+			multiple: false,
+		});
+		if (cacheDir) await moveCache(cacheDir);
+	}
+
+	async function remuxBundle() {
+		const bundle = await openDialog({
+			title: "Select a lecture bundle",
+			multiple: false,
+			filters: [{ name: "Lecture Bundle", extensions: ["tar"] }],
+		});
+		if (!bundle) return;
+
+		const output = await saveDialog({
+			title: "Save the lecture as",
+			filters: [{ name: "Video", extensions: ["mp4", "mkv", "mov", "webm"] }],
+		});
+		if (!output) return;
+
+		toast.info("Remuxing lecture...");
+		try {
+			await invoke("remux_bundle", { bundle, output });
+			toast.success("Remuxed lecture!");
+		} catch (e) {
+			toast.error(`Failed to remux the lecture: ${e}`);
+		}
+	}
+
 	async function saveSettings() {
 		try {
 			await invoke("save_settings", { settings });
 			toast.success("Saved settings successfully!");
 		} catch (e) {
-			toast.error("Failed to save settings due to an error! Please try again later.");
+			toast.error(`Failed to save settings: ${e}`);
 			console.error("Failed to save settings!", e);
 		}
 	}
@@ -96,6 +202,86 @@ export const SettingsDialog = () => {
         setSettings((prev) => ({ ...prev, format: (value && value.trim() ? value.trim() : null) }));
 	}
 
+	async function setCredentialStore(value: CredentialStore) {
+		setSettings((prev) => ({ ...prev, credential_store: value }));
+	}
+
+	async function setMinFreeSpace(value: string) {
+		const mib = parseInt(value);
+		setSettings((prev) => ({ ...prev, min_free_space: isNaN(mib) ? 0 : mib }));
+	}
+
+	async function setMaxConcurrentLectures(value: string) {
+		const count = parseInt(value);
+		setSettings((prev) => ({ ...prev, max_concurrent_lectures: isNaN(count) ? null : Math.max(count, 1) }));
+	}
+
+	async function setMaxCacheSize(value: string) {
+		const mib = parseInt(value);
+		setSettings((prev) => ({ ...prev, max_cache_size: isNaN(mib) ? null : mib }));
+	}
+
+	async function setBandwidth(changes: Partial<BandwidthLimit>) {
+		setSettings((prev) => ({
+			...prev,
+			bandwidth: {
+				limit: null,
+				schedule: [],
+				requests_per_sec: null,
+				...prev.bandwidth,
+				...changes,
+			},
+		}));
+	}
+
+	async function setScheduledLimit(index: number, changes: Partial<ScheduledLimit>) {
+		const schedule = (settings.bandwidth?.schedule ?? []).map((scheduled, i) =>
+			i === index ? { ...scheduled, ...changes } : scheduled,
+		);
+		setBandwidth({ schedule });
+	}
+
+	async function setSyncSchedule(changes: Partial<SyncSchedule>) {
+		setSettings((prev) => ({
+			...prev,
+			sync_schedule: {
+				on_launch: false,
+				daily_at: null,
+				window: null,
+				allow_metered: false,
+				...prev.sync_schedule,
+				...changes,
+			},
+		}));
+	}
+
+	async function setHttp(changes: Partial<HttpConfig>) {
+		setSettings((prev) => ({
+			...prev,
+			http: {
+				proxy: null,
+				root_certificates: [],
+				connect_timeout: null,
+				read_timeout: null,
+				user_agent: null,
+				...prev.http,
+				...changes,
+			},
+		}));
+	}
+
+	async function addCertificate() {
+		const certificate = await openDialog({
+			title: "Select a root certificate",
+			multiple: false,
+			filters: [{ name: "PEM Certificate", extensions: ["pem", "crt", "cer"] }],
+		});
+		if (!certificate) return;
+		setHttp({
+			root_certificates: [...(settings.http?.root_certificates ?? []), certificate],
+		});
+	}
+
 	async function setBase(value: string) {
 		setSettings((prev) => ({
 			...prev,
@@ -136,6 +322,101 @@ export const SettingsDialog = () => {
 								/>
 						</div>
 
+						{/* Offline */}
+						<div className="flex items-center gap-3">
+							<Checkbox
+								id="offline"
+								checked={settings.offline}
+								onCheckedChange={(checked) =>
+									setSettings((prev) => ({ ...prev, offline: !!checked }))
+								}
+							/>
+							<label htmlFor="offline">
+								<b>Offline Mode</b>
+								<p className="text-xs">
+									Finishes lectures only from what's already in the cache.
+									<br />
+									Lectures that weren't fully downloaded before are reported as failed
+								</p>
+							</label>
+						</div>
+
+						{/* Bandwidth */}
+						<div className="flex flex-row items-center gap-4 justify-between">
+							<div>
+								<b>Speed Limit</b>
+								<p className="text-xs">
+									In KB/s, shared by all downloads
+									<br />
+									Keep empty for no limit
+								</p>
+							</div>
+							<input type="number" min={1} placeholder="No limit" className="border-2 rounded py-2 px-3 outline-0 w-48 text-sm" value={settings.bandwidth?.limit ?? ""} onInput={(e) => { const limit = parseInt(e.currentTarget.value); setBandwidth({ limit: isNaN(limit) ? null : limit }); }}/>
+						</div>
+						<div className="flex flex-col gap-2">
+							<div>
+								<b>Scheduled Speed Limits</b>
+								<p className="text-xs">
+									Take over from the speed limit between two hours of the day, from 0 to 23
+									<br />
+									The first one that matches is used. Keep the limit empty for no limit
+								</p>
+							</div>
+							{settings.bandwidth?.schedule.map((scheduled, i) => (
+								<div key={i} className="flex items-center gap-2">
+									<input type="number" min={0} max={23} placeholder="From" className="border-2 rounded py-2 px-3 outline-0 w-full text-sm" value={scheduled.window[0]} onInput={(e) => { const hour = parseInt(e.currentTarget.value); setScheduledLimit(i, { window: [isNaN(hour) ? 0 : hour, scheduled.window[1]] }); }}/>
+									<input type="number" min={0} max={23} placeholder="To" className="border-2 rounded py-2 px-3 outline-0 w-full text-sm" value={scheduled.window[1]} onInput={(e) => { const hour = parseInt(e.currentTarget.value); setScheduledLimit(i, { window: [scheduled.window[0], isNaN(hour) ? 0 : hour] }); }}/>
+									<input type="number" min={1} placeholder="No limit" className="border-2 rounded py-2 px-3 outline-0 w-full text-sm" value={scheduled.limit ?? ""} onInput={(e) => { const limit = parseInt(e.currentTarget.value); setScheduledLimit(i, { limit: isNaN(limit) ? null : limit }); }}/>
+									<Button size="sm" variant="destructive" onClick={() => setBandwidth({ schedule: settings.bandwidth!.schedule.filter((_, j) => j !== i) })}>
+										Remove
+									</Button>
+								</div>
+							))}
+							<Button variant="secondary" onClick={() => setBandwidth({ schedule: [...(settings.bandwidth?.schedule ?? []), { window: [22, 6], limit: null }] })}>
+								Add Scheduled Limit
+							</Button>
+						</div>
+						<div className="flex flex-row items-center gap-4 justify-between">
+							<div>
+								<b>Request Limit</b>
+								<p className="text-xs">
+									Requests sent to each download source per second
+									<br />
+									Keep empty for no limit
+								</p>
+							</div>
+							<input type="number" min={1} placeholder="No limit" className="border-2 rounded py-2 px-3 outline-0 w-48 text-sm" value={settings.bandwidth?.requests_per_sec ?? ""} onInput={(e) => { const rate = parseFloat(e.currentTarget.value); setBandwidth({ requests_per_sec: isNaN(rate) ? null : rate }); }}/>
+						</div>
+
+						{/* Network */}
+						<div className="flex flex-col gap-2">
+							<div>
+								<b>Network</b>
+								<p className="text-xs">
+									For networks that need a proxy or their own certificates
+									<br />
+									Proxies can be <span className="font-mono">http://</span> or <span className="font-mono">socks5://</span>. Timeouts are in seconds, 5 and 30 by default
+								</p>
+							</div>
+							<input type="text" placeholder="Proxy, eg. socks5://127.0.0.1:1080" className="border-2 rounded py-2 px-3 outline-0 w-full text-sm" value={settings.http?.proxy ?? ""} onInput={(e) => setHttp({ proxy: e.currentTarget.value.trim() || null })}/>
+							<input type="text" placeholder="User agent" className="border-2 rounded py-2 px-3 outline-0 w-full text-sm" value={settings.http?.user_agent ?? ""} onInput={(e) => setHttp({ user_agent: e.currentTarget.value.trim() || null })}/>
+							<div className="flex gap-2">
+								<input type="number" min={1} placeholder="Connect timeout" className="border-2 rounded py-2 px-3 outline-0 w-full text-sm" value={settings.http?.connect_timeout ?? ""} onInput={(e) => { const secs = parseInt(e.currentTarget.value); setHttp({ connect_timeout: isNaN(secs) ? null : secs }); }}/>
+								<input type="number" min={1} placeholder="Read timeout" className="border-2 rounded py-2 px-3 outline-0 w-full text-sm" value={settings.http?.read_timeout ?? ""} onInput={(e) => { const secs = parseInt(e.currentTarget.value); setHttp({ read_timeout: isNaN(secs) ? null : secs }); }}/>
+							</div>
+							{settings.http?.root_certificates.map((certificate) => (
+								<div key={certificate} className="flex items-center justify-between gap-2 text-xs font-mono break-all">
+									{certificate}
+									<Button size="sm" variant="destructive" onClick={() => setHttp({ root_certificates: settings.http!.root_certificates.filter((c) => c !== certificate) })}>
+										Remove
+									</Button>
+								</div>
+							))}
+							<Button variant="secondary" onClick={addCertificate}>
+								Add Root Certificate
+							</Button>
+						</div>
+
 						{/* Remote */}
 						<div className="flex items-center gap-4 justify-between">
 							<div>
@@ -180,6 +461,155 @@ export const SettingsDialog = () => {
 							<input type="text" placeholder="Enter format specifier" className="border-2 rounded py-2 px-3 outline-0 w-full text-sm" value={settings.format ?? ""}  onInput={(e) => setFormat(e.currentTarget.value)}/> 
 						</div>
 
+						{/* Credential store */}
+						<div className="flex flex-row items-center gap-4 justify-between">
+							<div>
+								<b>Remember Login</b>
+								<p className="text-xs">
+									Lets downloads log in again by themselves
+									<br />
+									<b>Keyring:</b> Your system's password manager
+									<br />
+									<b>Encrypted File:</b> A file in the app's data folder, for systems without a password manager
+								</p>
+							</div>
+							<SelectCredentialStore
+								onValueChange={setCredentialStore}
+								value={settings.credential_store}
+							/>
+						</div>
+
+						{/* Minimum free space */}
+						<div className="flex flex-row items-center gap-4 justify-between">
+							<div>
+								<b>Minimum Free Space</b>
+								<p className="text-xs">
+									Downloads pause when less than this many MiB are free
+								</p>
+							</div>
+							<input type="number" min={0} className="border-2 rounded py-2 px-3 outline-0 w-48 text-sm" value={settings.min_free_space} onInput={(e) => setMinFreeSpace(e.currentTarget.value)}/>
+						</div>
+
+						{/* Concurrent lectures */}
+						<div className="flex flex-row items-center gap-4 justify-between">
+							<div>
+								<b>Lectures At Once</b>
+								<p className="text-xs">
+									How many lectures download together, the rest wait
+									<br />
+									in the queue and can be reordered while downloading
+									<br />
+									Keep empty to download 3 at a time
+								</p>
+							</div>
+							<input type="number" min={1} placeholder="3 (default)" className="border-2 rounded py-2 px-3 outline-0 w-48 text-sm" value={settings.max_concurrent_lectures ?? ""} onInput={(e) => setMaxConcurrentLectures(e.currentTarget.value)}/>
+						</div>
+
+						{/* Archive */}
+						<div className="flex flex-row items-center gap-4 justify-between">
+							<div className="flex items-center gap-3">
+								<Checkbox
+									id="archive"
+									checked={settings.archive}
+									onCheckedChange={(checked) =>
+										setSettings((prev) => ({ ...prev, archive: !!checked }))
+									}
+								/>
+								<label htmlFor="archive">
+									<b>Archive Chunks</b>
+									<p className="text-xs">
+										Keeps the original video chunks in a bundle
+										<br />
+										next to each lecture, to remux it again later
+									</p>
+								</label>
+							</div>
+							<Button variant="secondary" onClick={remuxBundle}>
+								Remux Bundle
+							</Button>
+						</div>
+
+						{/* Library verification */}
+						<LibraryVerifier />
+
+						{/* Sync schedule */}
+						<div className="flex flex-col gap-2">
+							<div>
+								<b>Sync Schedule</b>
+								<p className="text-xs">
+									When subscriptions are synced in the background, hours go from 0 to 23
+									<br />
+									Keep the window empty to sync at any hour
+								</p>
+							</div>
+							<div className="flex items-center gap-3">
+								<Checkbox
+									id="sync-on-launch"
+									checked={settings.sync_schedule?.on_launch ?? false}
+									onCheckedChange={(checked) => setSyncSchedule({ on_launch: !!checked })}
+								/>
+								<label htmlFor="sync-on-launch" className="text-sm">Sync when the app starts</label>
+							</div>
+							<div className="flex items-center gap-3">
+								<Checkbox
+									id="sync-metered"
+									checked={settings.sync_schedule?.allow_metered ?? false}
+									onCheckedChange={(checked) => setSyncSchedule({ allow_metered: !!checked })}
+								/>
+								<label htmlFor="sync-metered" className="text-sm">Sync on metered connections</label>
+							</div>
+							<input type="number" min={0} max={23} placeholder="Daily at, eg. 3" className="border-2 rounded py-2 px-3 outline-0 w-full text-sm" value={settings.sync_schedule?.daily_at ?? ""} onInput={(e) => { const hour = parseInt(e.currentTarget.value); setSyncSchedule({ daily_at: isNaN(hour) ? null : hour }); }}/>
+							<div className="flex items-center gap-2">
+								<input type="number" min={0} max={23} placeholder="Window from" className="border-2 rounded py-2 px-3 outline-0 w-full text-sm" value={settings.sync_schedule?.window?.[0] ?? ""} onInput={(e) => { const hour = parseInt(e.currentTarget.value); setSyncSchedule({ window: isNaN(hour) ? null : [hour, settings.sync_schedule?.window?.[1] ?? (hour + 1) % 24] }); }}/>
+								<input type="number" min={0} max={23} placeholder="Window to" className="border-2 rounded py-2 px-3 outline-0 w-full text-sm" value={settings.sync_schedule?.window?.[1] ?? ""} onInput={(e) => { const hour = parseInt(e.currentTarget.value); setSyncSchedule({ window: isNaN(hour) ? null : [settings.sync_schedule?.window?.[0] ?? (hour + 23) % 24, hour] }); }}/>
+							</div>
+						</div>
+
+						{/* Subscriptions */}
+						<Subscriptions />
+
+						{/* Cache location */}
+						<div className="flex flex-row items-center gap-4 justify-between">
+							<div>
+								<b>Cache Location</b>
+								<p className="text-xs break-all">
+									Where lectures are kept while downloading
+									<br />
+									<span className="font-mono">{settings.cache_dir ?? "System temporary folder"}</span>
+								</p>
+							</div>
+							<div className="flex gap-2">
+								<Button variant="secondary" onClick={pickCacheDir}>
+									Move
+								</Button>
+								<Button
+									variant="secondary"
+									disabled={settings.cache_dir == null}
+									onClick={() => moveCache(null)}
+								>
+									Reset
+								</Button>
+							</div>
+						</div>
+
+						{/* Max cache size */}
+						<div className="flex flex-row items-center gap-4 justify-between">
+							<div>
+								<b>Maximum Cache Size</b>
+								<p className="text-xs">
+									In MiB. Unfinished lectures that have not been used
+									<br />
+									recently are removed when the cache grows past this
+									<br />
+									Keep empty for no limit
+								</p>
+							</div>
+							<input type="number" min={0} placeholder="No limit" className="border-2 rounded py-2 px-3 outline-0 w-48 text-sm" value={settings.max_cache_size ?? ""} onInput={(e) => setMaxCacheSize(e.currentTarget.value)}/>
+						</div>
+
+						{/* Cached lectures */}
+						<CacheList onChange={computeCache} />
+
 						{/* Clear cache */}
 						<div className="flex gap-4">
 							<Button
@@ -233,6 +663,27 @@ function SelectQuality({ ...props }: React.ComponentProps<typeof Select>) {
 	);
 }
 
+function SelectCredentialStore({ ...props }: React.ComponentProps<typeof Select>) {
+	return (
+		<Select {...props}>
+			<SelectTrigger className="text-nowrap w-48 h-10 select-none py-2 place-self-center border-2 ">
+				<SelectValue placeholder="Select Store" />
+			</SelectTrigger>
+			<SelectContent>
+				<SelectItem value={CredentialStore.None} key={0} className="py-2">
+					Don't Remember
+				</SelectItem>
+				<SelectItem value={CredentialStore.Keyring} key={1} className="py-2">
+					Keyring
+				</SelectItem>
+				<SelectItem value={CredentialStore.EncryptedFile} key={2} className="py-2">
+					Encrypted File
+				</SelectItem>
+			</SelectContent>
+		</Select>
+	);
+}
+
 function SelectRemotes({ ...props }: React.ComponentProps<typeof Select>) {
 	let bases: string[] = JSON.parse(import.meta.env.VITE_REMOTES);
 
@@ -12,8 +12,9 @@ import {
 	PopoverContent,
 	PopoverTrigger,
 } from "@/components/ui/popover";
-import { subjectAtom } from "@/lib/atoms";
-import { fetchLex } from "@/lib/lex";
+import { subjectAtom, subjectDetailsAtom } from "@/lib/atoms";
+import { logtoClient } from "@/lib/logto";
+import { invoke } from "@tauri-apps/api/core";
 import { useSetAtom } from "jotai";
 import { ChevronDownIcon } from "lucide-react";
 import { useEffect, useState } from "react";
@@ -21,6 +22,7 @@ import { useDebounce } from "use-debounce";
 
 export const SubjectSelector = () => {
 	const setSelectedSubject = useSetAtom(subjectAtom);
+	const setSubjectDetails = useSetAtom(subjectDetailsAtom);
 	const [label, setLabel] = useState("Select Subject");
 	const [open, setOpen] = useState(false);
 	const [search, setSearch] = useState("");
@@ -33,9 +35,14 @@ export const SubjectSelector = () => {
 
 	useEffect(() => {
 		setLoading(true);
-		fetchLex<Multipartus.Subject[]>(
-			`subject/search?q=${encodeURIComponent(debouncedSearch)}`,
-		)
+		logtoClient
+			.getIdToken()
+			.then((token) =>
+				invoke<Multipartus.Subject[]>("search_subjects", {
+					token,
+					query: debouncedSearch,
+				}),
+			)
 			.then(setSubjects)
 			.finally(() => setLoading(false));
 	}, [debouncedSearch]);
@@ -65,6 +72,7 @@ export const SubjectSelector = () => {
 									value={subject.id.ID.join(";")}
 									onSelect={() => {
 										setSelectedSubject(subject.id.ID);
+										setSubjectDetails(subject);
 										setLabel(formatSubject(subject));
 										setOpen(false);
 									}}
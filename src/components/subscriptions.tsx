@@ -0,0 +1,169 @@
+import { logtoClient } from "@/lib/logto";
+import { Channel, invoke } from "@tauri-apps/api/core";
+import { useEffect, useState } from "react";
+import { toast } from "sonner";
+import { Button } from "./ui/button";
+
+type Subscription = {
+	subject: Multipartus.Subject;
+	lecture: Multipartus.Lecture;
+	folder: string;
+};
+
+type SubscriptionReport = {
+	subject: string;
+	section: string;
+	folder: string;
+	missing: { ttid: number; number: number; topic: string; location: string }[];
+	downloaded: number[];
+	error: string | null;
+};
+
+type SyncReport = {
+	dryRun: boolean;
+	subscriptions: SubscriptionReport[];
+};
+
+type SyncRun = {
+	trigger: "Launch" | "Daily";
+	startedAt: string;
+	finishedAt: string;
+	report: SyncReport | null;
+	error: string | null;
+};
+
+// Summarizes a background sync, eg. "downloaded 3 new lectures", or why it failed
+function summarize({ report, error }: SyncRun) {
+	if (error) return error;
+	const reports = report?.subscriptions ?? [];
+	const failed = reports.filter(({ error }) => error).length;
+	const downloaded = reports.reduce((sum, { downloaded }) => sum + downloaded.length, 0);
+	return `downloaded ${downloaded} new lectures${failed > 0 ? `, ${failed} subscriptions failed` : ""}`;
+}
+
+export const Subscriptions = () => {
+	const [subscriptions, setSubscriptions] = useState<Subscription[]>([]);
+	const [report, setReport] = useState<SyncReport | null>(null);
+	const [syncing, setSyncing] = useState(false);
+	const [history, setHistory] = useState<SyncRun[]>([]);
+
+	async function refresh() {
+		try {
+			setSubscriptions(await invoke("list_subscriptions"));
+			setHistory(await invoke("get_sync_history"));
+		} catch (e) {
+			console.error("Failed to list subscriptions!", e);
+		}
+	}
+
+	useEffect(() => {
+		refresh();
+	}, []);
+
+	async function unsubscribe(lecture: [number, number]) {
+		try {
+			await invoke("unsubscribe", { lecture });
+			await refresh();
+		} catch (e) {
+			toast.error(`${e}`);
+		}
+	}
+
+	async function sync(dryRun: boolean) {
+		setSyncing(true);
+		setReport(null);
+		const token = await logtoClient.getIdToken();
+		const onError = new Channel<{ errors: [string, string] }>();
+		onError.onmessage = ({ errors }) => toast.error(`${errors[0]}: ${errors[1]}`);
+
+		try {
+			const report: SyncReport = await invoke("sync", {
+				token,
+				dryRun,
+				onStart: new Channel(),
+				onProgress: new Channel(),
+				onError,
+			});
+			setReport(report);
+		} catch (e) {
+			toast.error(`${e}`);
+		}
+		setSyncing(false);
+	}
+
+	return (
+		<div className="flex flex-col gap-2">
+			<div>
+				<b>Subscriptions</b>
+				<p className="text-xs">
+					New lectures of these sections are downloaded to their folders when syncing
+					<br />
+					Subscribe to a section from the bell next to the download button
+				</p>
+			</div>
+			{subscriptions.map(({ subject, lecture, folder }) => (
+				<div
+					key={lecture.id.ID.join(";")}
+					className="flex items-center justify-between gap-2 text-xs border rounded-sm p-2"
+				>
+					<div>
+						<b>
+							{subject.department} {subject.code} - {subject.name}
+						</b>{" "}
+						{lecture.section} | {lecture.professor}
+						<br />
+						{folder}
+					</div>
+					<Button
+						size="sm"
+						variant="secondary"
+						onClick={() => unsubscribe(lecture.id.ID)}
+					>
+						Unsubscribe
+					</Button>
+				</div>
+			))}
+			<div className="flex gap-2">
+				<Button
+					variant="secondary"
+					onClick={() => sync(true)}
+					disabled={syncing || subscriptions.length === 0}
+				>
+					Check for New Lectures
+				</Button>
+				<Button
+					onClick={() => sync(false)}
+					disabled={syncing || subscriptions.length === 0}
+				>
+					{syncing ? "Syncing..." : "Sync Now"}
+				</Button>
+			</div>
+			{report?.subscriptions.map(({ subject, section, missing, downloaded, error }) => (
+				<p key={`${subject} ${section}`} className="text-xs">
+					<b>
+						{subject} {section}:
+					</b>{" "}
+					{error
+						? <span className="text-red-800">{error}</span>
+						: report.dryRun
+						? `${missing.length} new lectures`
+						: `downloaded ${downloaded.length} of ${missing.length} new lectures`}
+				</p>
+			))}
+			{history.length > 0 && (
+				<div className="text-xs max-h-28 overflow-auto">
+					<b>Background Syncs</b>
+					{[...history].reverse().map((run) => (
+						<p key={run.startedAt}>
+							{new Date(run.startedAt).toLocaleString()} (
+							{run.trigger === "Launch" ? "on launch" : "daily"}):{" "}
+							<span className={run.error ? "text-red-800" : undefined}>
+								{summarize(run)}
+							</span>
+						</p>
+					))}
+				</div>
+			)}
+		</div>
+	);
+};
@@ -1,5 +1,6 @@
 import { lectureAtom, subjectAtom, videosAtom } from "@/lib/atoms";
 import { fetchLex } from "@/lib/lex";
+import { markDownloaded } from "@/lib/library";
 import { openUrl } from "@tauri-apps/plugin-opener";
 import { type PrimitiveAtom, useAtom, useAtomValue, useSetAtom } from "jotai";
 import { splitAtom } from "jotai/utils";
@@ -35,6 +36,11 @@ const VideoItem = (props: { video: PrimitiveAtom<Multipartus.Video> }) => {
 						{video.number}
 					</span>
 					{video.topic}
+					{video.downloaded && (
+						<span className="text-xs text-muted-foreground border px-1 rounded-sm">
+							Downloaded
+						</span>
+					)}
 				</div>
 				<div className="inline-flex gap-4">
 					<span className="text-sm text-muted-foreground">
@@ -79,6 +85,7 @@ export const VideoSelector = () => {
 						startTime: new Date(video.startTime).toLocaleDateString("en-IN"),
 					})),
 				)
+				.then(markDownloaded)
 				.then(setVideo);
 		}
 	}, [lecture]);
@@ -23,11 +23,12 @@ namespace Multipartus {
 	export interface Video {
 		ttid: number;
 		topic: string;
+		subjectName: string;
 		startTime: string;
 		selected: boolean;
 		number: number;
 		subjectID: [string, string];
-		
+		downloaded?: boolean;
 	}
 
 	export type Sessions = Record<string, [number, number]>;
@@ -3,6 +3,9 @@ import { atom } from "jotai";
 // selected subject
 export const subjectAtom = atom<[string, string]>();
 
+// name and code of the selected subject, for subscribing to it
+export const subjectDetailsAtom = atom<Multipartus.Subject>();
+
 // selected lecture section for the selected subject
 export const lectureAtom = atom<[number, number]>();
 
@@ -0,0 +1,14 @@
+import { invoke } from "@tauri-apps/api/core";
+
+// marks the videos that are already in the downloaded library
+export async function markDownloaded(
+	videos: Multipartus.Video[],
+): Promise<Multipartus.Video[]> {
+	const downloaded: number[] = await invoke("get_downloaded", {
+		ttids: videos.map((video) => video.ttid),
+	});
+	return videos.map((video) => ({
+		...video,
+		downloaded: downloaded.includes(video.ttid),
+	}));
+}
@@ -1,5 +1,6 @@
 import LogtoClient, { createRequester, Prompt } from "@logto/browser";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import { fetch } from "@tauri-apps/plugin-http";
 import { openUrl } from "@tauri-apps/plugin-opener";
 import {
@@ -38,6 +39,11 @@ export const LogtoProvider = ({ children }: { children?: ReactNode }) => {
 	async function updateAuthState() {
 		if (await logtoClient.isAuthenticated()) {
 			await logtoClient.getAccessToken();
+			// Lets the backend log in by itself, if the user has opted into storing credentials
+			const refreshToken = await logtoClient.getRefreshToken();
+			if (refreshToken) {
+				await invoke("store_credentials", { refreshToken });
+			}
 			setIsAuthenticated(true);
 		} else {
 			setIsAuthenticated(false);
@@ -55,6 +61,17 @@ export const LogtoProvider = ({ children }: { children?: ReactNode }) => {
 		});
 	}, []);
 
+	useEffect(() => {
+		// Downloads ask for a fresh token when theirs has expired
+		const unlisten = listen("token-expired", async () => {
+			await logtoClient.getAccessToken();
+			await invoke("set_token", { token: await logtoClient.getIdToken() });
+		});
+		return () => {
+			unlisten.then((f) => f());
+		};
+	}, []);
+
 	useEffect(() => {
 		if (isAuthenticated) {
 			navigate("/app");